@@ -1,4 +1,9 @@
 CREATE TYPE quest_state AS ENUM ('pending', 'verified', 'denied');
+CREATE TYPE quest_repeat AS ENUM ('once', 'daily', 'weekly', 'interval');
+CREATE TYPE spin_state AS ENUM ('spun', 'accepted', 'completed', 'skipped');
+-- Same categories as the questionnaire on the frontend
+CREATE TYPE challenge_category AS ENUM ('mental', 'digital', 'nature', 'routine', 'selfcare');
+CREATE TYPE difficulty AS ENUM ('easy', 'medium', 'hard');
 
 CREATE TABLE users (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
@@ -11,15 +16,102 @@ CREATE TABLE users (
     longest_streak integer DEFAULT 0 NOT NULL,
     current_streak integer DEFAULT 0 NOT NULL,
     completed_weekly date DEFAULT NULL,
-    banned boolean NOT NULL DEFAULT false
+    banned boolean NOT NULL DEFAULT false,
+    streak_freezes integer DEFAULT 0 NOT NULL CHECK (streak_freezes >= 0),
+    show_on_leaderboard boolean DEFAULT true NOT NULL,
+    -- Public profile, `name` is only shown to the user themselves and admins
+    display_name VARCHAR(50) DEFAULT NULL,
+    avatar VARCHAR(20) DEFAULT NULL,
+    alias VARCHAR(50) DEFAULT 'Участник ' || (1000 + floor(random() * 9000))::integer NOT NULL,
+    anonymous boolean DEFAULT false NOT NULL,
+    friends_only boolean DEFAULT false NOT NULL
 );
 
+CREATE UNIQUE INDEX users_display_name_idx ON users (lower(display_name));
+
+-- What other users see of `u`, anonymous users only get their alias
+CREATE FUNCTION public_name(u users) RETURNS varchar AS $$
+    SELECT CASE WHEN u.anonymous THEN u.alias ELSE COALESCE(u.display_name, u.alias) END;
+$$ LANGUAGE sql STABLE;
+
+CREATE FUNCTION public_avatar(u users) RETURNS varchar AS $$
+    SELECT CASE WHEN u.anonymous THEN NULL ELSE u.avatar END;
+$$ LANGUAGE sql STABLE;
+
+-- Whether `u` shows up on boards that everyone can see
+CREATE FUNCTION on_public_leaderboard(u users) RETURNS boolean AS $$
+    SELECT NOT u.banned AND NOT u.is_admin AND u.show_on_leaderboard AND NOT u.friends_only;
+$$ LANGUAGE sql STABLE;
+
 CREATE TABLE quests (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
     name VARCHAR(50) NOT NULL,
     description VARCHAR(250) NOT NULL,
     points_received integer NOT NULL,
-    required_points integer NOT NULL
+    required_points integer NOT NULL,
+    -- How often a user may complete the quest again. 'interval' uses repeat_interval_days.
+    repeat_policy quest_repeat NOT NULL DEFAULT 'once',
+    repeat_interval_days integer DEFAULT NULL CHECK (repeat_interval_days > 0),
+    -- NULL means no limit on the number of completions
+    max_completions integer DEFAULT NULL CHECK (max_completions > 0),
+
+    -- Multi-day quests are done through that many check-ins instead of in one go
+    required_checkins integer DEFAULT 1 NOT NULL CHECK (required_checkins > 0),
+    -- Award a share of the points on every check-in instead of everything at the end
+    prorate_points boolean DEFAULT false NOT NULL,
+    -- When false the user has to send proof and an admin verifies it
+    self_complete boolean DEFAULT true NOT NULL,
+
+    CONSTRAINT quests_repeat_interval_check
+        CHECK (repeat_policy <> 'interval' OR repeat_interval_days IS NOT NULL)
+);
+
+-- Optional ordered steps of a multi-step quest, one per check-in (position starts at 1)
+CREATE TABLE quest_steps (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    quest_id UUID NOT NULL,
+    position integer NOT NULL CHECK (position > 0),
+    name VARCHAR(50) NOT NULL,
+    description VARCHAR(250) NOT NULL,
+
+    UNIQUE (quest_id, position),
+
+    CONSTRAINT quest_steps_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE TABLE campaigns (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    description VARCHAR(250) NOT NULL,
+    starts_at timestamptz NOT NULL,
+    ends_at timestamptz NOT NULL,
+    points_multiplier real DEFAULT 1 NOT NULL CHECK (points_multiplier > 0),
+
+    CONSTRAINT campaigns_time_check CHECK (ends_at > starts_at)
+);
+
+-- Quests that belong to a campaign are only offered while one of their campaigns runs
+CREATE TABLE campaign_quests (
+    campaign_id UUID NOT NULL,
+    quest_id UUID NOT NULL,
+
+    PRIMARY KEY (campaign_id, quest_id),
+
+    CONSTRAINT campaign_quests_campaign_id_fkey
+        FOREIGN KEY (campaign_id)
+        REFERENCES campaigns(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT campaign_quests_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
 );
 
 CREATE TABLE user_quest (
@@ -29,6 +121,9 @@ CREATE TABLE user_quest (
     completed_at date DEFAULT NOW(),
     proof_path TEXT,
     progress quest_state,
+    points_awarded integer DEFAULT 0 NOT NULL,
+    -- The campaign that was running when the quest was done, used for campaign leaderboards
+    campaign_id UUID DEFAULT NULL,
 
 
     CONSTRAINT user_quest_user_id_fkey
@@ -38,19 +133,761 @@ CREATE TABLE user_quest (
         ON UPDATE RESTRICT,
     
     CONSTRAINT user_quest_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT user_quest_campaign_id_fkey
+        FOREIGN KEY (campaign_id)
+        REFERENCES campaigns(id)
+        ON DELETE SET NULL
+        ON UPDATE RESTRICT
+);
+
+-- A multi-step quest the user has started but not finished yet
+CREATE TABLE quest_progress (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    quest_id UUID NOT NULL,
+    checkins integer DEFAULT 0 NOT NULL,
+    started_at timestamptz DEFAULT NOW() NOT NULL,
+    last_checkin date DEFAULT NOW() NOT NULL,
+
+    UNIQUE (user_id, quest_id),
+
+    CONSTRAINT quest_progress_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT quest_progress_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+-- A quest can be done at most once a day, which also stops double submits
+CREATE UNIQUE INDEX user_quest_once_a_day_idx ON user_quest (user_id, quest_id, completed_at)
+    WHERE progress IS DISTINCT FROM 'denied';
+
+-- Enforces the repeat policy of the quest for every new user_quest row
+CREATE FUNCTION user_quest_repeat_check() RETURNS trigger AS $$
+DECLARE
+    q quests%ROWTYPE;
+    done integer;
+    last_done date;
+    cooldown integer;
+BEGIN
+    SELECT * INTO q FROM quests WHERE id = NEW.quest_id;
+    SELECT COUNT(*), MAX(completed_at) INTO done, last_done FROM user_quest
+        WHERE user_id = NEW.user_id AND quest_id = NEW.quest_id AND progress IS DISTINCT FROM 'denied';
+
+    IF done = 0 THEN
+        RETURN NEW;
+    END IF;
+    IF q.max_completions IS NOT NULL AND done >= q.max_completions THEN
+        RAISE EXCEPTION 'quest % was completed the maximum number of times', q.id USING ERRCODE = 'check_violation';
+    END IF;
+
+    cooldown := CASE q.repeat_policy
+        WHEN 'daily' THEN 1
+        WHEN 'weekly' THEN 7
+        WHEN 'interval' THEN q.repeat_interval_days
+    END;
+    IF cooldown IS NULL OR last_done + cooldown > COALESCE(NEW.completed_at, CURRENT_DATE) THEN
+        RAISE EXCEPTION 'quest % is not available again yet', q.id USING ERRCODE = 'check_violation';
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER user_quest_repeat_check BEFORE INSERT ON user_quest
+    FOR EACH ROW EXECUTE FUNCTION user_quest_repeat_check();
+
+-- Remembers the outcome of requests sent with an Idempotency-Key header so retries don't award points twice
+CREATE TABLE idempotency_keys (
+    user_id UUID NOT NULL,
+    key VARCHAR(255) NOT NULL,
+    status_code integer DEFAULT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+
+    PRIMARY KEY (user_id, key),
+
+    CONSTRAINT idempotency_keys_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE TABLE wheel_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    title VARCHAR(50) NOT NULL,
+    description VARCHAR(250) NOT NULL,
+    -- Disabled challenges stay in the table but are not on the wheel
+    enabled boolean DEFAULT true NOT NULL,
+    -- Order of the segments on the wheel
+    position integer DEFAULT 0 NOT NULL,
+    points integer DEFAULT 30 NOT NULL CHECK (points >= 0),
+    -- Relative size of the segment when drawing, before personalisation
+    weight real DEFAULT 1 NOT NULL CHECK (weight > 0),
+    category challenge_category DEFAULT 'mental' NOT NULL,
+    difficulty difficulty DEFAULT 'medium' NOT NULL
+);
+
+-- Every spin of the wheel. A spin is accepted and then completed, or skipped.
+CREATE TABLE wheel_spins (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    challenge_id UUID DEFAULT NULL,
+    -- Copied so the history still makes sense when the challenge is deleted
+    title VARCHAR(50) NOT NULL,
+    state spin_state DEFAULT 'spun' NOT NULL,
+    points_awarded integer DEFAULT 0 NOT NULL,
+    -- The random number the segment was drawn with
+    seed bigint DEFAULT NULL,
+    spun_at timestamptz DEFAULT NOW() NOT NULL,
+    updated_at timestamptz DEFAULT NOW() NOT NULL,
+
+    CONSTRAINT wheel_spins_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT wheel_spins_challenge_id_fkey
+        FOREIGN KEY (challenge_id)
+        REFERENCES wheel_challenges(id)
+        ON DELETE SET NULL
+        ON UPDATE RESTRICT
+);
+
+-- A user finishes (or skips) their spin before spinning again
+CREATE UNIQUE INDEX wheel_spins_open_idx ON wheel_spins (user_id) WHERE state IN ('spun', 'accepted');
+
+-- Per category results of the daily questionnaire, 0 (struggling) to 4 (doing well)
+CREATE TABLE questionnaire_scores (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    category challenge_category NOT NULL,
+    score integer NOT NULL CHECK (score BETWEEN 0 AND 4),
+    answered_at timestamptz DEFAULT NOW() NOT NULL,
+
+    CONSTRAINT questionnaire_scores_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE TYPE activity_kind AS ENUM ('quest', 'wheel', 'diary', 'questionnaire', 'goal');
+
+-- The days a user was active and what counted on them, streaks are derived from this
+CREATE TABLE activity_days (
+    user_id UUID NOT NULL,
+    day date NOT NULL,
+    kind activity_kind NOT NULL,
+    count integer DEFAULT 1 NOT NULL,
+    first_at timestamptz DEFAULT NOW() NOT NULL,
+
+    PRIMARY KEY (user_id, day, kind),
+
+    CONSTRAINT activity_days_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+-- What counts as being active. An activity counts when its rule is enabled and its amount reaches min_amount:
+-- 1 per quest or wheel completion, characters for diary entries and goal descriptions, categories answered for the questionnaire.
+CREATE TABLE activity_rules (
+    kind activity_kind PRIMARY KEY NOT NULL,
+    enabled boolean DEFAULT true NOT NULL,
+    min_amount integer DEFAULT 1 NOT NULL CHECK (min_amount >= 0),
+    updated_at timestamptz DEFAULT NOW() NOT NULL
+);
+
+INSERT INTO activity_rules (kind, enabled, min_amount) VALUES
+    ('quest', true, 1),
+    ('wheel', true, 1),
+    ('diary', true, 50),
+    ('questionnaire', true, 1),
+    ('goal', true, 1);
+
+-- Missed days that didn't break the streak, covered by a freeze or repaired afterwards
+CREATE TABLE streak_freeze_days (
+    user_id UUID NOT NULL,
+    day date NOT NULL,
+    repaired boolean DEFAULT false NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+
+    PRIMARY KEY (user_id, day),
+
+    CONSTRAINT streak_freeze_days_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+-- Every change to a user's points, leaderboards for a period are summed from this
+CREATE TABLE points_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    points integer NOT NULL,
+    awarded_at timestamptz DEFAULT NOW() NOT NULL,
+
+    CONSTRAINT points_history_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+-- The days (in UTC) a user got points for the questionnaire, only the first one of a day gives any
+CREATE TABLE questionnaire_rewards (
+    user_id UUID NOT NULL,
+    day date NOT NULL,
+
+    PRIMARY KEY (user_id, day),
+
+    CONSTRAINT questionnaire_rewards_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX points_history_awarded_at_idx ON points_history (awarded_at, user_id);
+
+-- One row per level a user reached, written when an award crosses a level threshold
+CREATE TABLE level_ups (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    level integer NOT NULL,
+    reached_at timestamptz DEFAULT NOW() NOT NULL,
+
+    CONSTRAINT level_ups_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+-- What an achievement counts, see handlers/achievements.rs
+CREATE TYPE achievement_metric AS ENUM ('streak', 'quests', 'wheel', 'weekly', 'diary', 'questionnaire', 'level');
+
+-- An achievement unlocks once its metric reaches the threshold. `category` narrows down wheel challenges.
+CREATE TABLE achievements (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    code VARCHAR(50) NOT NULL UNIQUE,
+    name VARCHAR(100) NOT NULL,
+    description VARCHAR(255) NOT NULL,
+    metric achievement_metric NOT NULL,
+    category challenge_category,
+    threshold integer NOT NULL CHECK (threshold > 0),
+    position integer DEFAULT 0 NOT NULL
+);
+
+CREATE TABLE user_achievements (
+    user_id UUID NOT NULL,
+    achievement_id UUID NOT NULL,
+    unlocked_at timestamptz DEFAULT NOW() NOT NULL,
+
+    PRIMARY KEY (user_id, achievement_id),
+
+    CONSTRAINT user_achievements_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT user_achievements_achievement_id_fkey
+        FOREIGN KEY (achievement_id)
+        REFERENCES achievements(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE TYPE friendship_status AS ENUM ('pending', 'accepted', 'blocked');
+
+-- One row per pair of users. `requester_id` sent the request, or is the one who blocked the other.
+CREATE TABLE friendships (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    requester_id UUID NOT NULL,
+    addressee_id UUID NOT NULL,
+    status friendship_status DEFAULT 'pending' NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+    updated_at timestamptz DEFAULT NOW() NOT NULL,
+
+    CHECK (requester_id <> addressee_id),
+
+    CONSTRAINT friendships_requester_id_fkey
+        FOREIGN KEY (requester_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT friendships_addressee_id_fkey
+        FOREIGN KEY (addressee_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE UNIQUE INDEX friendships_pair_idx ON friendships (LEAST(requester_id, addressee_id), GREATEST(requester_id, addressee_id));
+CREATE INDEX friendships_addressee_idx ON friendships (addressee_id);
+
+CREATE FUNCTION are_friends(a UUID, b UUID) RETURNS boolean AS $$
+    SELECT EXISTS(SELECT 1 FROM friendships f WHERE f.status = 'accepted'
+        AND ((f.requester_id = a AND f.addressee_id = b) OR (f.requester_id = b AND f.addressee_id = a)));
+$$ LANGUAGE sql STABLE;
+
+CREATE TYPE group_role AS ENUM ('owner', 'admin', 'member');
+
+-- Classes, clubs and other teams. New members join with the invite code.
+CREATE TABLE groups (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    name VARCHAR(50) NOT NULL,
+    description VARCHAR(255) DEFAULT '' NOT NULL,
+    invite_code VARCHAR(8) DEFAULT upper(substr(md5(random()::text), 1, 8)) NOT NULL UNIQUE,
+    created_at timestamptz DEFAULT NOW() NOT NULL
+);
+
+CREATE TABLE group_members (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    group_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    role group_role DEFAULT 'member' NOT NULL,
+    joined_at timestamptz DEFAULT NOW() NOT NULL,
+
+    UNIQUE (group_id, user_id),
+
+    CONSTRAINT group_members_group_id_fkey
+        FOREIGN KEY (group_id)
+        REFERENCES groups(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT group_members_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE UNIQUE INDEX group_members_owner_idx ON group_members (group_id) WHERE role = 'owner';
+CREATE INDEX group_members_user_id_idx ON group_members (user_id);
+
+-- A quest the group does together, it's completed once `required_members` members completed the quest after it was set
+CREATE TABLE group_quests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    group_id UUID NOT NULL,
+    quest_id UUID NOT NULL,
+    required_members integer NOT NULL CHECK (required_members > 0),
+    -- Given to every member who took part once it's completed
+    bonus_points integer DEFAULT 0 NOT NULL CHECK (bonus_points >= 0),
+    deadline timestamptz DEFAULT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+    completed_at timestamptz DEFAULT NULL,
+
+    CONSTRAINT group_quests_group_id_fkey
+        FOREIGN KEY (group_id)
+        REFERENCES groups(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT group_quests_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE TYPE group_event_kind AS ENUM ('joined', 'left', 'quest_completed', 'group_quest_completed');
+
+-- The group feed
+CREATE TABLE group_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    group_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    kind group_event_kind NOT NULL,
+    quest_id UUID DEFAULT NULL,
+    -- Not NOW(), events of the same transaction keep their order
+    created_at timestamptz DEFAULT clock_timestamp() NOT NULL,
+
+    CONSTRAINT group_events_group_id_fkey
+        FOREIGN KEY (group_id)
+        REFERENCES groups(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT group_events_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT group_events_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX group_events_group_id_idx ON group_events (group_id, created_at);
+
+-- 'expired' isn't stored, open challenges past their deadline are shown as expired
+CREATE TYPE peer_challenge_status AS ENUM ('pending', 'accepted', 'declined', 'completed', 'expired');
+
+-- A user challenging a friend to a quest or a wheel challenge. Completions count once it's accepted.
+CREATE TABLE peer_challenges (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    challenger_id UUID NOT NULL,
+    challenged_id UUID NOT NULL,
+    quest_id UUID DEFAULT NULL,
+    wheel_challenge_id UUID DEFAULT NULL,
+    message VARCHAR(255) DEFAULT NULL,
+    deadline timestamptz DEFAULT NULL,
+    status peer_challenge_status DEFAULT 'pending' NOT NULL,
+    -- Given to both once both completed it
+    bonus_points integer NOT NULL,
+    challenger_done_at timestamptz DEFAULT NULL,
+    challenged_done_at timestamptz DEFAULT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+    responded_at timestamptz DEFAULT NULL,
+    completed_at timestamptz DEFAULT NULL,
+
+    CHECK ((quest_id IS NULL) <> (wheel_challenge_id IS NULL)),
+    CHECK (challenger_id <> challenged_id),
+
+    CONSTRAINT peer_challenges_challenger_id_fkey
+        FOREIGN KEY (challenger_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT peer_challenges_challenged_id_fkey
+        FOREIGN KEY (challenged_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT peer_challenges_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT peer_challenges_wheel_challenge_id_fkey
+        FOREIGN KEY (wheel_challenge_id)
+        REFERENCES wheel_challenges(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX peer_challenges_challenger_idx ON peer_challenges (challenger_id);
+CREATE INDEX peer_challenges_challenged_idx ON peer_challenges (challenged_id);
+
+CREATE TYPE activity_event_kind AS ENUM ('quest_completed', 'weekly_completed', 'achievement_unlocked', 'streak_milestone');
+
+-- What users did, shown to their friends and groups in /api/feed
+CREATE TABLE activity_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    kind activity_event_kind NOT NULL,
+    quest_id UUID DEFAULT NULL,
+    user_quest_id UUID DEFAULT NULL,
+    achievement_id UUID DEFAULT NULL,
+    streak integer DEFAULT NULL,
+    -- Hidden by the user, nobody else sees it
+    hidden boolean DEFAULT false NOT NULL,
+    created_at timestamptz DEFAULT clock_timestamp() NOT NULL,
+
+    CONSTRAINT activity_events_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT activity_events_quest_id_fkey
+        FOREIGN KEY (quest_id)
+        REFERENCES quests(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT activity_events_user_quest_id_fkey
+        FOREIGN KEY (user_quest_id)
+        REFERENCES user_quest(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT activity_events_achievement_id_fkey
+        FOREIGN KEY (achievement_id)
+        REFERENCES achievements(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX activity_events_user_id_idx ON activity_events (user_id, created_at);
+
+-- Other users' events a user doesn't want in their feed
+CREATE TABLE feed_hidden (
+    user_id UUID NOT NULL,
+    event_id UUID NOT NULL,
+
+    PRIMARY KEY (user_id, event_id),
+
+    CONSTRAINT feed_hidden_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT feed_hidden_event_id_fkey
+        FOREIGN KEY (event_id)
+        REFERENCES activity_events(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE TYPE reaction_kind AS ENUM ('clap', 'heart', 'fire', 'muscle', 'seedling', 'smile');
+
+-- Reactions and comments are on feed events, completions through their quest_completed/weekly_completed event
+CREATE TABLE event_reactions (
+    event_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    reaction reaction_kind NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+
+    PRIMARY KEY (event_id, user_id, reaction),
+
+    CONSTRAINT event_reactions_event_id_fkey
+        FOREIGN KEY (event_id)
+        REFERENCES activity_events(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT event_reactions_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX event_reactions_user_id_idx ON event_reactions (user_id, created_at);
+
+CREATE TABLE event_comments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    event_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    content VARCHAR(280) NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+
+    CONSTRAINT event_comments_event_id_fkey
+        FOREIGN KEY (event_id)
+        REFERENCES activity_events(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT event_comments_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX event_comments_event_id_idx ON event_comments (event_id, created_at);
+CREATE INDEX event_comments_user_id_idx ON event_comments (user_id, created_at);
+
+CREATE TYPE feed_action AS ENUM ('comment', 'reaction');
+
+-- What the rate limits on comments and reactions count. Rows are only added, so deleting a comment
+-- or taking a reaction back doesn't free up the limit again.
+CREATE TABLE rate_limit_log (
+    user_id UUID NOT NULL,
+    action feed_action NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+
+    CONSTRAINT rate_limit_log_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX rate_limit_log_user_id_idx ON rate_limit_log (user_id, action, created_at);
+
+-- Comments reported to the admins, resolved ones stay for the record
+CREATE TABLE comment_reports (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    comment_id UUID NOT NULL,
+    reporter_id UUID NOT NULL,
+    reason VARCHAR(255) DEFAULT '' NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+    resolved_at timestamptz DEFAULT NULL,
+
+    UNIQUE (comment_id, reporter_id),
+
+    CONSTRAINT comment_reports_comment_id_fkey
+        FOREIGN KEY (comment_id)
+        REFERENCES event_comments(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT comment_reports_reporter_id_fkey
+        FOREIGN KEY (reporter_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE TYPE notification_kind AS ENUM ('quest_verified', 'quest_denied', 'banned', 'unbanned', 'weekly_quest', 'reminder');
+
+CREATE TABLE notifications (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    kind notification_kind NOT NULL,
+    title VARCHAR(100) NOT NULL,
+    body VARCHAR(500) DEFAULT '' NOT NULL,
+    quest_id UUID DEFAULT NULL,
+    created_at timestamptz DEFAULT clock_timestamp() NOT NULL,
+    read_at timestamptz DEFAULT NULL,
+
+    CONSTRAINT notifications_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT,
+
+    CONSTRAINT notifications_quest_id_fkey
         FOREIGN KEY (quest_id)
         REFERENCES quests(id)
         ON DELETE CASCADE
         ON UPDATE RESTRICT
 );
 
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, created_at);
+CREATE INDEX notifications_unread_idx ON notifications (user_id) WHERE read_at IS NULL;
+
+-- Kinds without a row here are on
+CREATE TABLE notification_preferences (
+    user_id UUID NOT NULL,
+    kind notification_kind NOT NULL,
+    enabled bool NOT NULL,
+
+    PRIMARY KEY (user_id, kind),
+
+    CONSTRAINT notification_preferences_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+-- Every user gets a row with the defaults when they register, times are in the user's time zone
+CREATE TABLE reminder_preferences (
+    user_id UUID PRIMARY KEY NOT NULL,
+    timezone VARCHAR(64) DEFAULT 'Europe/Sofia' NOT NULL,
+    questionnaire bool DEFAULT false NOT NULL,
+    questionnaire_time time DEFAULT '20:00' NOT NULL,
+    streak_at_risk bool DEFAULT true NOT NULL,
+    streak_warning_time time DEFAULT '20:00' NOT NULL,
+    weekly_challenge bool DEFAULT true NOT NULL,
+    goal_due bool DEFAULT true NOT NULL,
+    email bool DEFAULT false NOT NULL,
+
+    CONSTRAINT reminder_preferences_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE FUNCTION create_reminder_preferences() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO reminder_preferences (user_id) VALUES (NEW.id);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER create_reminder_preferences AFTER INSERT ON users
+    FOR EACH ROW EXECUTE FUNCTION create_reminder_preferences();
+
+CREATE TYPE reminder_kind AS ENUM ('questionnaire', 'streak_at_risk', 'weekly_challenge', 'goal_due');
+
+-- One reminder of a kind per day in the user's time zone, weekly ones are on the Monday of the week
+CREATE TABLE reminders_sent (
+    user_id UUID NOT NULL,
+    kind reminder_kind NOT NULL,
+    day date NOT NULL,
+    sent_at timestamptz DEFAULT NOW() NOT NULL,
+
+    PRIMARY KEY (user_id, kind, day),
+
+    CONSTRAINT reminders_sent_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+-- Mail for a mailer to send, the backend doesn't talk SMTP itself
+CREATE TABLE email_outbox (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    recipient VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    body TEXT NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+    sent_at timestamptz DEFAULT NULL
+);
+
+CREATE INDEX email_outbox_unsent_idx ON email_outbox (created_at) WHERE sent_at IS NULL;
+
+-- One per browser or device, `endpoint` and the keys come from the browser's PushSubscription
+CREATE TABLE push_subscriptions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    endpoint TEXT UNIQUE NOT NULL,
+    p256dh VARCHAR(100) NOT NULL,
+    auth VARCHAR(50) NOT NULL,
+    user_agent VARCHAR(255) DEFAULT '' NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+    expires_at timestamptz DEFAULT NULL,
+    last_success_at timestamptz DEFAULT NULL,
+    -- Deliveries that failed in a row
+    failures integer DEFAULT 0 NOT NULL,
+
+    CONSTRAINT push_subscriptions_user_id_fkey
+        FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+        ON UPDATE RESTRICT
+);
+
+CREATE INDEX push_subscriptions_user_id_idx ON push_subscriptions (user_id);
+
+CREATE TYPE goal_status AS ENUM ('active', 'completed', 'archived');
+CREATE TYPE goal_category AS ENUM ('personal', 'health', 'work', 'learning', 'fitness', 'mindfulness');
+
 CREATE TABLE personal_challanges (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
     name VARCHAR(50) NOT NULL,
     description VARCHAR(255) NOT NULL,
     user_id UUID NOT NULL,
-    priority INT NOT NULL,
-    category VARCHAR(40) NOT NULL,
+    -- 1 is the most important
+    priority INT NOT NULL CHECK (priority BETWEEN 1 AND 5),
+    category goal_category NOT NULL,
+    due_date date DEFAULT NULL,
+    status goal_status DEFAULT 'active' NOT NULL,
+    created_at timestamptz DEFAULT NOW() NOT NULL,
+    completed_at timestamptz DEFAULT NULL,
+
+    CHECK ((status = 'completed') = (completed_at IS NOT NULL)),
 
     CONSTRAINT user_quest_user_id_fkey
         FOREIGN KEY (user_id)
@@ -240,3 +1077,115 @@ INSERT INTO quests (name, description, points_received, required_points) VALUES
 ('Седмица без излишни покупки', 'Купувай само необходимото и наблюдавай импулсите си за харчене.', 50, 0),
 ('Седмица грижа за себе си', 'Всеки ден прави по едно малко действие, което те зарежда и подкрепя.', 50, 0),
 ('Седмица наблюдение на навици', 'Без да ги променяш, просто наблюдавай ежедневните си навици и реакции.', 50, 0);
+
+-- Quests that ask for a photo are checked by an admin, admins can change this per quest
+UPDATE quests SET self_complete = false WHERE name IN ('„Слънчев заряд“', '„Спокойната стъпка“', '„Природна контролна точка“', '„Балон на фокуса“');
+
+-- Small everyday quests can be repeated, bigger ones only once in a while.
+UPDATE quests SET repeat_policy = 'daily' WHERE points_received = 10;
+UPDATE quests SET repeat_policy = 'weekly' WHERE points_received = 15;
+UPDATE quests SET repeat_policy = 'interval', repeat_interval_days = 30, max_completions = 3 WHERE points_received = 30;
+UPDATE quests SET repeat_policy = 'weekly' WHERE points_received = 50;
+
+INSERT INTO quests (name, description, points_received, required_points, repeat_policy, repeat_interval_days, required_checkins, prorate_points) VALUES
+('Осъзнати сутрини', '10 минути осъзнатост всяка сутрин в продължение на 7 дни.', 30, 30, 'interval', 30, 7, true),
+('30 дни благодарност', 'Записвай по една благодарност всеки ден в продължение на 30 дни.', 30, 30, 'interval', 60, 30, true);
+
+INSERT INTO quests (name, description, points_received, required_points, repeat_policy, repeat_interval_days, required_checkins) VALUES
+('Рестарт на съня', 'Изгради спокойна вечерна рутина стъпка по стъпка.', 30, 30, 'interval', 30, 3);
+
+INSERT INTO quest_steps (quest_id, position, name, description)
+SELECT id, s.position, s.name, s.description FROM quests, (VALUES
+    (1, 'Фиксиран час', 'Избери час за лягане и го спази.'),
+    (2, 'Без екрани', 'Без екрани 1 час преди сън.'),
+    (3, 'Вечерен ритуал', 'Добави спокоен ритуал преди сън – четене, дишане или разтягане.')
+) AS s(position, name, description)
+WHERE quests.name = 'Рестарт на съня';
+
+INSERT INTO wheel_challenges (title, description, position) VALUES
+('24-часов дигитален залез', 'Без екрани от залез до изгрев; напиши кратък размисъл.', 1),
+('Одит на отпадъците', 'Сортирай домакинските отпадъци за 48 ч и замени един предмет за еднократна употреба.', 2),
+('Разходка и слушане', '90-минутна разходка без навигация на телефона; отбележи 10 растения или животни.', 3),
+('Ден без оплаквания', 'Превръщай оплакванията в конструктивни действия за един ден.', 4),
+('Проект за ремонт', 'Поправи счупен предмет или го рециклирай отговорно.', 5),
+('1 час за зелена кауза', 'Стани доброволец за един час в местна екологична група.', 6),
+('Пост от социални мрежи', '72 часа без една социална мрежа; напиши писмо на ръка до приятел.', 7),
+('Научи някого', 'Научи някого на практично зелено умение (компостиране, ремонт).', 8),
+('Местна кухня', 'Сготви ястие само с местни сезонни продукти.', 9),
+('Дълбок разговор', '30-минутен разговор без разсейване с човек, на когото държиш.', 10),
+('24 часа без пластмаса', 'Избягвай пластмаса за еднократна употреба цял ден.', 11),
+('Осъзнати сутрини', '10 минути осъзнатост всяка сутрин в продължение на 7 дни.', 12),
+('Рестарт на съня', 'Следвай режим на сън 7 нощи; без екрани 1 час преди лягане.', 13),
+('Размяна в общността', 'Организирай или се включи в размяна на дрехи, инструменти или книги.', 14),
+('Обяд без отпадъци x3', 'Приготви обяд без отпадъци три дни подред.', 15),
+('Разговор с непознат', 'Проведи уважителен 10–15-минутен разговор с някого нов.', 16),
+('30 дни благодарност', 'Записвай по една благодарност всеки ден в продължение на 30 дни.', 17),
+('Малко местообитание', 'Направи сандъче с растения за опрашители и го наблюдавай седмица.', 18),
+('Енергиен одит', 'Прецени най-големите разходи на енергия и въведи 3 намаления.', 19),
+('Нов живот на остатъците', 'Превърни остатъците от храна в ново творческо ястие.', 20),
+('Труден разговор', 'Планирай и проведи спокоен разговор, който отлагаш.', 21),
+('Градски транспорт', 'Използвай градски транспорт, колело или ходене два поредни дни.', 22),
+('Ден на местната икономика', 'Купувай само местни продукти за един ден.', 23),
+('Природен микропроект', 'Засей местни семена или направи хотел за пчели.', 24),
+('Седмица без приложение', 'Блокирай пристрастяващо приложение за една седмица.', 25),
+('Разказ от парка', '90 минути навън, после напиши разказ от 500 думи.', 26),
+('Ъпсайклинг предизвикателство', 'Превърни стар предмет в нещо ново за подарък.', 27),
+('Вечер на осъзнато слушане', 'Проведи едночасова сесия на слушане с приятел.', 28),
+('Ремонтно кафе', 'Посети или организирай събитие за поправяне на вещи.', 29),
+('Минималист за ден', 'Живей само с 20 предмета за един ден.', 30),
+('Творчески спринт без екрани', '2 часа творчество без никакви екрани.', 31),
+('Писмо за природата', 'Изпрати кратко, аргументирано писмо до местен представител на властта.', 32),
+('Грижа за приятелство', 'Избери едно приятелство, в което да вложиш време този месец.', 33);
+
+UPDATE wheel_challenges w SET category = v.category::challenge_category, difficulty = v.difficulty::difficulty FROM (VALUES
+    (1, 'digital', 'hard'),
+    (2, 'nature', 'medium'),
+    (3, 'nature', 'medium'),
+    (4, 'mental', 'medium'),
+    (5, 'routine', 'medium'),
+    (6, 'nature', 'medium'),
+    (7, 'digital', 'hard'),
+    (8, 'nature', 'medium'),
+    (9, 'selfcare', 'easy'),
+    (10, 'mental', 'easy'),
+    (11, 'nature', 'medium'),
+    (12, 'mental', 'medium'),
+    (13, 'selfcare', 'hard'),
+    (14, 'nature', 'medium'),
+    (15, 'nature', 'medium'),
+    (16, 'mental', 'medium'),
+    (17, 'mental', 'hard'),
+    (18, 'nature', 'medium'),
+    (19, 'routine', 'medium'),
+    (20, 'selfcare', 'easy'),
+    (21, 'mental', 'hard'),
+    (22, 'nature', 'easy'),
+    (23, 'routine', 'easy'),
+    (24, 'nature', 'easy'),
+    (25, 'digital', 'hard'),
+    (26, 'nature', 'medium'),
+    (27, 'routine', 'medium'),
+    (28, 'mental', 'easy'),
+    (29, 'routine', 'medium'),
+    (30, 'routine', 'hard'),
+    (31, 'digital', 'medium'),
+    (32, 'nature', 'easy'),
+    (33, 'mental', 'easy')
+) AS v(position, category, difficulty)
+WHERE w.position = v.position;
+
+INSERT INTO achievements (code, name, description, metric, category, threshold, position) VALUES
+    ('first_quest', 'Първа стъпка', 'Завърши първото си предизвикателство.', 'quests', NULL, 1, 1),
+    ('quests_25', 'Упорит', 'Завърши 25 предизвикателства.', 'quests', NULL, 25, 2),
+    ('quests_100', 'Неудържим', 'Завърши 100 предизвикателства.', 'quests', NULL, 100, 3),
+    ('first_weekly', 'Седмичен герой', 'Завърши първото си седмично предизвикателство.', 'weekly', NULL, 1, 4),
+    ('streak_7', 'Седмица подред', 'Бъди активен 7 дни подред.', 'streak', NULL, 7, 5),
+    ('streak_30', 'Месец подред', 'Бъди активен 30 дни подред.', 'streak', NULL, 30, 6),
+    ('nature_10', 'Приятел на природата', 'Завърши 10 предизвикателства от колелото сред природата.', 'wheel', 'nature', 10, 7),
+    ('digital_10', 'Дигитален детокс', 'Завърши 10 предизвикателства от колелото за дигитален баланс.', 'wheel', 'digital', 10, 8),
+    ('wheel_25', 'Късметлия', 'Завърши 25 предизвикателства от колелото.', 'wheel', NULL, 25, 9),
+    ('diary_1', 'Първа страница', 'Напиши първия си запис в дневника.', 'diary', NULL, 1, 10),
+    ('diary_30', 'Летописец', 'Напиши 30 записа в дневника.', 'diary', NULL, 30, 11),
+    ('questionnaire_7', 'Самонаблюдение', 'Попълни въпросника в 7 различни дни.', 'questionnaire', NULL, 7, 12),
+    ('level_5', 'Ниво 5', 'Достигни ниво 5.', 'level', NULL, 5, 13),
+    ('level_10', 'Ниво 10', 'Достигни ниво 10.', 'level', NULL, 10, 14);
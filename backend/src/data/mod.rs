@@ -1,7 +1,7 @@
-use std::sync::Arc;
+use std::{collections::{HashMap, HashSet}, sync::{Arc, atomic::AtomicU64}, time::{Duration, Instant}};
 
 use sqlx::types::chrono;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
 use uuid::Uuid;
 
 
@@ -11,6 +11,96 @@ pub struct AppState {
     pub db_connection: sqlx::PgPool,
     pub weekly_challange: Arc<Mutex<Option<Quest>>>,
     pub last_week: Arc<Mutex<Option<u32>>>,
+    pub wheel_challenges: Arc<Mutex<Option<WheelCache>>>,
+    pub wheel_spins_per_day: i64,
+    pub level_curve: LevelCurve,
+    pub streak_freeze_price: i32,
+    pub leaderboards: Arc<Mutex<LeaderboardCache>>,
+    pub leaderboard_writes: Arc<LeaderboardWrites>,
+    pub leaderboard_max_staleness: Duration,
+    pub profanity: Arc<HashSet<String>>,
+    pub events: EventHub,
+    pub push: Option<Arc<WebPush>>,
+    pub push_mock: Option<Arc<MockPush>>,
+}
+
+// The application server's VAPID key and contact, see push.rs
+pub struct WebPush {
+    pub key_pair: ring::signature::EcdsaKeyPair,
+    // Uncompressed point, base64url
+    pub public_key: String,
+    pub subject: String,
+    pub client: reqwest::Client,
+}
+
+// The mock push service, its endpoints are `{url}/{id}`
+pub struct MockPush {
+    // Where this server's /push/mock is reachable, without the trailing slash
+    pub url: String,
+    pub endpoints: Mutex<HashMap<Uuid, MockPushEndpoint>>,
+}
+
+// A push service endpoint of the mock push service, it answers every message with `status`
+#[derive(Debug)]
+pub struct MockPushEndpoint {
+    pub status: u16,
+    pub received: Vec<MockPushMessage>,
+}
+
+// Fans live events out to the open /api/events streams of this instance. Events only get in through
+// `events::publish`, so running several instances only needs that to go through Postgres NOTIFY and a
+// LISTEN task that feeds `sender`.
+#[derive(Clone)]
+pub struct EventHub {
+    pub sender: broadcast::Sender<(Uuid, LiveEvent)>,
+}
+
+// Level 1 needs `base_xp` points to finish, every level after that `growth` times more than the one before
+#[derive(Clone, Copy, Debug)]
+pub struct LevelCurve {
+    pub base_xp: i32,
+    pub growth: f64,
+}
+
+// A snapshot per period, see handlers/leaderboard.rs
+#[derive(Default)]
+pub struct LeaderboardCache {
+    pub boards: HashMap<LeaderboardPeriod, LeaderboardSnapshot>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// Transactions with point awards that are committing right now, and a counter bumped whenever one is done or the
+// boards are invalidated. A board built while either moved may have missed points or would get them twice.
+#[derive(Default)]
+pub struct LeaderboardWrites {
+    pub committing: AtomicU64,
+    pub generation: AtomicU64,
+}
+
+// Ranked users of one period, best first, and where each user is in `standings`
+pub struct LeaderboardSnapshot {
+    pub built: Instant,
+    pub start: Option<chrono::DateTime<chrono::Utc>>,
+    pub standings: Vec<Standing>,
+    pub index: HashMap<Uuid, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Standing {
+    pub user_id: Uuid,
+    // Unique, only used to break ties
+    pub name: String,
+    pub public_name: String,
+    pub avatar: Option<String>,
+    pub points: i32,
+    pub total_points: i32,
+}
+
+// Enabled wheel challenges and when they were loaded
+pub struct WheelCache {
+    pub loaded: Instant,
+    pub challenges: Vec<WheelChallenge>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug)]
@@ -29,6 +119,69 @@ pub struct User {
     pub last_active: chrono::NaiveDate,
     pub completed_weekly: Option<chrono::NaiveDate>,
     pub banned: Option<bool>,
+    pub streak_freezes: i32,
+    pub show_on_leaderboard: bool,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub alias: String,
+    pub anonymous: bool,
+    pub friends_only: bool,
+}
+
+// What the user shows of themselves to others. `public_name` is the name they currently appear under.
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct PrivacySettings {
+    pub show_on_leaderboard: bool,
+    pub anonymous: bool,
+    pub friends_only: bool,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+    pub alias: String,
+    pub public_name: String,
+}
+
+// Empty `display_name` or `avatar` clear them
+#[derive(serde::Deserialize, Debug)]
+pub struct PrivacySettingsInput {
+    pub show_on_leaderboard: Option<bool>,
+    pub anonymous: Option<bool>,
+    pub friends_only: Option<bool>,
+    pub display_name: Option<String>,
+    pub avatar: Option<String>,
+}
+
+// `current_streak` is 0 once a missed day wasn't covered, even before the next activity resets it
+#[derive(serde::Serialize, Debug)]
+pub struct StreakStatus {
+    pub current_streak: i32,
+    pub longest_streak: i32,
+    pub last_completed_date: Option<chrono::NaiveDate>,
+    pub freezes_available: i32,
+    pub freezes_used: i64,
+    pub repairs_left: i64,
+    pub can_repair: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct Me {
+    #[serde(flatten)]
+    pub user: User,
+    #[serde(flatten)]
+    pub level: Level,
+    pub quests_in_progress: Vec<QuestProgress>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Level {
+    pub level: i32,
+    pub xp_into_level: i32,
+    pub xp_for_next_level: i32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug)]
+pub struct LevelUp {
+    pub level: i32,
+    pub reached_at: chrono::DateTime<chrono::Utc>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug)]
@@ -40,13 +193,116 @@ pub enum Progress {
     Denied
 }
 
-#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Default)]
+#[sqlx(type_name="quest_repeat")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum RepeatPolicy {
+    #[default]
+    Once,
+    Daily,
+    Weekly,
+    Interval,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug, Clone, Default)]
 pub struct Quest {
     pub id: Uuid,
     pub name: String,
     pub description: String,
     pub required_points: i32,
     pub points_received: i32,
+    pub repeat_policy: RepeatPolicy,
+    pub repeat_interval_days: Option<i32>,
+    pub max_completions: Option<i32>,
+    pub required_checkins: i32,
+    pub prorate_points: bool,
+    pub self_complete: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug, Clone)]
+pub struct QuestStep {
+    pub name: String,
+    pub description: String,
+}
+
+// How far a user got in a multi-step quest
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+pub struct QuestProgress {
+    pub quest_id: Uuid,
+    pub name: String,
+    pub checkins: i32,
+    pub required_checkins: i32,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub last_checkin: chrono::NaiveDate,
+    pub next_step: Option<String>,
+    pub next_step_description: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct CheckinResult {
+    #[serde(flatten)]
+    pub progress: QuestProgress,
+    pub completed: bool,
+    pub points_awarded: i32,
+}
+
+// A quest offered to a user together with their history on it.
+// `available_at` is set when the quest is cooling down and can't be done yet.
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+pub struct OfferedQuest {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub quest: Quest,
+    pub completions: i64,
+    pub last_completed: Option<chrono::NaiveDate>,
+    #[sqlx(skip)]
+    pub available_at: Option<chrono::NaiveDate>,
+    // Set when the quest is part of a running campaign
+    pub campaign_id: Option<Uuid>,
+    pub points_multiplier: Option<f32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, sqlx::FromRow)]
+pub struct Campaign {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub starts_at: chrono::DateTime<chrono::Utc>,
+    pub ends_at: chrono::DateTime<chrono::Utc>,
+    pub points_multiplier: f32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct CampaignInput {
+    pub name: String,
+    pub description: String,
+    pub starts_at: chrono::DateTime<chrono::Utc>,
+    pub ends_at: chrono::DateTime<chrono::Utc>,
+    pub points_multiplier: Option<f32>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct CampaignDetails {
+    #[serde(flatten)]
+    pub campaign: Campaign,
+    pub quests: Vec<Quest>,
+}
+
+#[derive(serde::Serialize, Debug, Clone, sqlx::FromRow)]
+pub struct CampaignStanding {
+    pub name: String,
+    pub avatar: Option<String>,
+    pub points: i64,
+    pub rank: i64,
+    pub is_me: bool,
+}
+
+// `me` is empty for callers that aren't on the board, they can be on it below `entries`
+#[derive(serde::Serialize, Debug)]
+pub struct CampaignLeaderboard {
+    pub entries: Vec<CampaignStanding>,
+    pub me: Option<CampaignStanding>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -69,21 +325,80 @@ pub struct VerifyRequest {
 
 
 
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Default)]
+#[sqlx(type_name="goal_status")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum GoalStatus {
+    #[default]
+    Active,
+    Completed,
+    Archived,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="goal_category")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum GoalCategory {
+    Personal,
+    Health,
+    Work,
+    Learning,
+    Fitness,
+    Mindfulness,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, sqlx::FromRow)]
 pub struct PersonalChallange {
     pub id: Uuid,
     pub description: String,
     pub user_id: Uuid,
     pub name: String,
-    pub category: String,
+    pub category: GoalCategory,
     pub priority: i32,
+    pub due_date: Option<chrono::NaiveDate>,
+    pub status: GoalStatus,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct PersonalChallangeInput {
     pub description: String,
     pub name: String,
-    pub category: String,
+    pub category: GoalCategory,
     pub priority: i32,
+    pub due_date: Option<chrono::NaiveDate>,
+}
+
+// Only the sent fields change, clear_due_date removes the due date
+#[derive(serde::Deserialize, Debug)]
+pub struct PersonalChallangeUpdate {
+    pub description: Option<String>,
+    pub name: Option<String>,
+    pub category: Option<GoalCategory>,
+    pub priority: Option<i32>,
+    pub due_date: Option<chrono::NaiveDate>,
+    #[serde(default)]
+    pub clear_due_date: bool,
+    pub status: Option<GoalStatus>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all="snake_case")]
+pub enum GoalSort {
+    #[default]
+    Created,
+    Priority,
+    DueDate,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GoalQuery {
+    pub status: Option<GoalStatus>,
+    pub category: Option<GoalCategory>,
+    #[serde(default)]
+    pub sort: GoalSort,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -100,4 +415,744 @@ pub struct DiaryData {
     pub mood: String,
     pub date: chrono::NaiveDate,
     pub user_id: Uuid,
-}
\ No newline at end of file
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, sqlx::FromRow, Clone)]
+pub struct WheelChallenge {
+    pub id: Uuid,
+    pub title: String,
+    pub description: String,
+    pub enabled: bool,
+    pub position: i32,
+    pub points: i32,
+    pub weight: f32,
+    pub category: ChallengeCategory,
+    pub difficulty: Difficulty,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[sqlx(type_name="challenge_category")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum ChallengeCategory {
+    Mental,
+    Digital,
+    Nature,
+    Routine,
+    Selfcare,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="difficulty")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct WheelChallengeInput {
+    pub title: String,
+    pub description: String,
+    pub enabled: Option<bool>,
+    pub position: Option<i32>,
+    pub points: Option<i32>,
+    pub weight: Option<f32>,
+    pub category: Option<ChallengeCategory>,
+    pub difficulty: Option<Difficulty>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="spin_state")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum SpinState {
+    Spun,
+    Accepted,
+    Completed,
+    Skipped,
+}
+
+#[derive(serde::Serialize, Debug, sqlx::FromRow)]
+pub struct WheelSpin {
+    pub id: Uuid,
+    pub challenge_id: Option<Uuid>,
+    pub title: String,
+    pub state: SpinState,
+    pub points_awarded: i32,
+    pub seed: Option<i64>,
+    pub spun_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+// A segment of the wheel with the weight it had for this spin
+#[derive(serde::Serialize, Debug)]
+pub struct WheelSegment {
+    pub challenge_id: Uuid,
+    pub title: String,
+    pub category: ChallengeCategory,
+    pub difficulty: Difficulty,
+    pub weight: f64,
+}
+
+// `index` is the segment the wheel has to stop on, it follows from `segments` and `seed`
+#[derive(serde::Serialize, Debug)]
+pub struct SpinResult {
+    pub spin: WheelSpin,
+    pub index: usize,
+    pub segments: Vec<WheelSegment>,
+    pub seed: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="achievement_metric")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum AchievementMetric {
+    Streak,
+    Quests,
+    Wheel,
+    Weekly,
+    Diary,
+    Questionnaire,
+    Level,
+}
+
+// An achievement as the user sees it, `progress` stops at the threshold
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug)]
+pub struct AchievementStatus {
+    pub id: Uuid,
+    pub code: String,
+    pub name: String,
+    pub description: String,
+    pub metric: AchievementMetric,
+    pub category: Option<ChallengeCategory>,
+    pub threshold: i32,
+    pub progress: i32,
+    pub unlocked: bool,
+    pub unlocked_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="activity_kind")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum ActivityKind {
+    Quest,
+    Wheel,
+    Diary,
+    Questionnaire,
+    Goal,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug)]
+pub struct ActivityRule {
+    pub kind: ActivityKind,
+    pub enabled: bool,
+    pub min_amount: i32,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct ActivityRuleInput {
+    pub enabled: Option<bool>,
+    pub min_amount: Option<i32>,
+}
+
+// A day of the streak calendar, either active or kept by a freeze/repair
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct CalendarDay {
+    pub day: chrono::NaiveDate,
+    pub kinds: Vec<ActivityKind>,
+    pub activities: i64,
+    pub frozen: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct StreakCalendar {
+    pub month: String,
+    pub days: Vec<CalendarDay>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct CalendarQuery {
+    pub month: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all="lowercase")]
+pub enum LeaderboardPeriod {
+    Week,
+    Month,
+    #[default]
+    All,
+}
+
+// Friends boards rank the caller and their friends, including friends that only show themselves to friends
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all="lowercase")]
+pub enum LeaderboardScope {
+    #[default]
+    Global,
+    Friends,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct LeaderboardQuery {
+    #[serde(default)]
+    pub period: LeaderboardPeriod,
+    #[serde(default)]
+    pub scope: LeaderboardScope,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// `points` are the points earned in the period, `level` goes by all of the user's points
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub avatar: Option<String>,
+    pub points: i32,
+    pub level: i32,
+    pub rank: i64,
+    pub position: i64,
+    pub is_me: bool,
+}
+
+// `me` and `around_me` are empty for callers that aren't on the board
+#[derive(serde::Serialize, Debug)]
+pub struct Leaderboard {
+    pub period: LeaderboardPeriod,
+    pub scope: LeaderboardScope,
+    pub total: i64,
+    pub entries: Vec<LeaderboardEntry>,
+    pub me: Option<LeaderboardEntry>,
+    pub around_me: Vec<LeaderboardEntry>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct LeaderboardMetrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub boards: Vec<LeaderboardBoardMetrics>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct LeaderboardBoardMetrics {
+    pub period: LeaderboardPeriod,
+    pub age_secs: u64,
+    pub users: usize,
+}
+
+// Scores per questionnaire category, 0 to 4
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct QuestionnaireInput {
+    pub scores: std::collections::HashMap<ChallengeCategory, i32>,
+}
+
+// `id` is the friendship's, users are never referred to by their id
+#[derive(serde::Serialize, Debug)]
+pub struct Friend {
+    pub id: Uuid,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub current_streak: i32,
+    pub points: i32,
+    pub since: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct FriendRequest {
+    pub id: Uuid,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub incoming: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct BlockedUser {
+    pub id: Uuid,
+    pub name: String,
+    pub blocked_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct FriendRequestInput {
+    pub name: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="group_role")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum GroupRole {
+    Owner,
+    Admin,
+    Member,
+}
+
+// How member points add up to the group's score
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all="lowercase")]
+pub enum GroupAggregate {
+    #[default]
+    Sum,
+    Avg,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupInput {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupEditInput {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupJoinInput {
+    pub code: String,
+}
+
+// `invite_code` is only sent to owners and admins
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct GroupSummary {
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub role: GroupRole,
+    pub members: i64,
+    pub invite_code: Option<String>,
+}
+
+// `id` is the membership's, `points` are the points earned in the period
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct GroupMember {
+    pub id: Uuid,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub role: GroupRole,
+    pub points: i32,
+    pub joined_at: chrono::DateTime<chrono::Utc>,
+    pub is_me: bool,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct GroupDetails {
+    #[serde(flatten)]
+    pub group: GroupSummary,
+    pub period: LeaderboardPeriod,
+    pub aggregate: GroupAggregate,
+    pub score: f64,
+    pub member_list: Vec<GroupMember>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupScoreQuery {
+    #[serde(default)]
+    pub period: LeaderboardPeriod,
+    #[serde(default)]
+    pub aggregate: GroupAggregate,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct GroupStanding {
+    pub id: Uuid,
+    pub name: String,
+    pub members: i64,
+    pub score: f64,
+    pub rank: i64,
+    pub is_mine: bool,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupRoleInput {
+    pub role: GroupRole,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct GroupQuest {
+    pub id: Uuid,
+    pub quest_id: Uuid,
+    pub quest_name: String,
+    pub required_members: i32,
+    pub bonus_points: i32,
+    pub deadline: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub completions: i64,
+    pub completed_by_me: bool,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupQuestInput {
+    pub quest_id: Uuid,
+    pub required_members: i32,
+    pub bonus_points: Option<i32>,
+    pub deadline: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="group_event_kind")]
+#[sqlx(rename_all="snake_case")]
+#[serde(rename_all="snake_case")]
+pub enum GroupEventKind {
+    Joined,
+    Left,
+    QuestCompleted,
+    GroupQuestCompleted,
+}
+
+// Feed pages go back in time, `before` is the id of the last item of the previous page
+#[derive(serde::Deserialize, Debug)]
+pub struct GroupFeedQuery {
+    pub before: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct GroupFeedItem {
+    pub id: Uuid,
+    pub kind: GroupEventKind,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub quest_name: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="peer_challenge_status")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum PeerChallengeStatus {
+    Pending,
+    Accepted,
+    Declined,
+    Completed,
+    Expired,
+}
+
+// `friend` is the id of the friendship with the challenged user. Exactly one of `quest_id` and `wheel_challenge_id` is set.
+#[derive(serde::Deserialize, Debug)]
+pub struct PeerChallengeInput {
+    pub friend: Uuid,
+    pub quest_id: Option<Uuid>,
+    pub wheel_challenge_id: Option<Uuid>,
+    pub message: Option<String>,
+    pub deadline: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PeerChallengeQuery {
+    pub status: Option<PeerChallengeStatus>,
+}
+
+// `name` and `avatar` are what the other user shows to others, `incoming` is set on challenges the caller got
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct PeerChallenge {
+    pub id: Uuid,
+    pub incoming: bool,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub quest_id: Option<Uuid>,
+    pub wheel_challenge_id: Option<Uuid>,
+    pub title: String,
+    pub message: Option<String>,
+    pub deadline: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: PeerChallengeStatus,
+    pub bonus_points: i32,
+    pub my_done_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub friend_done_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="activity_event_kind")]
+#[sqlx(rename_all="snake_case")]
+#[serde(rename_all="snake_case")]
+pub enum ActivityEventKind {
+    QuestCompleted,
+    WeeklyCompleted,
+    AchievementUnlocked,
+    StreakMilestone,
+}
+
+// `before` is the `next` of the previous page
+#[derive(serde::Deserialize, Debug)]
+pub struct FeedQuery {
+    pub before: Option<Uuid>,
+    pub limit: Option<i64>,
+}
+
+// `hidden` can only be set on the caller's own events, others don't see them
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct FeedItem {
+    pub id: Uuid,
+    pub kind: ActivityEventKind,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub is_mine: bool,
+    pub quest_name: Option<String>,
+    pub user_quest_id: Option<Uuid>,
+    pub achievement_name: Option<String>,
+    pub streak: Option<i32>,
+    pub hidden: bool,
+    pub reactions: i64,
+    pub comments: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+// `next` is unset on the last page
+#[derive(serde::Serialize, Debug)]
+pub struct Feed {
+    pub items: Vec<FeedItem>,
+    pub next: Option<Uuid>,
+}
+
+// What a rate limit is kept for, see feed::rate_limit
+#[derive(sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="feed_action")]
+#[sqlx(rename_all="lowercase")]
+pub enum FeedAction {
+    Comment,
+    Reaction,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="reaction_kind")]
+#[sqlx(rename_all="lowercase")]
+#[serde(rename_all="lowercase")]
+pub enum ReactionKind {
+    Clap,
+    Heart,
+    Fire,
+    Muscle,
+    Seedling,
+    Smile,
+}
+
+// `mine` is set when the caller gave this reaction
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct ReactionCount {
+    pub reaction: ReactionKind,
+    pub count: i64,
+    pub mine: bool,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct CommentInput {
+    pub content: String,
+}
+
+// `can_delete` is set on the caller's comments and on every comment of the caller's own events
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct Comment {
+    pub id: Uuid,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub content: String,
+    pub is_mine: bool,
+    pub can_delete: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ReportInput {
+    pub reason: Option<String>,
+}
+
+// Admins see the login names of both users
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct CommentReport {
+    pub id: Uuid,
+    pub comment_id: Uuid,
+    pub content: String,
+    pub author: String,
+    pub reporter: String,
+    pub reason: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ResolveReportInput {
+    pub remove_comment: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="notification_kind")]
+#[sqlx(rename_all="snake_case")]
+#[serde(rename_all="snake_case")]
+pub enum NotificationKind {
+    QuestVerified,
+    QuestDenied,
+    Banned,
+    Unbanned,
+    WeeklyQuest,
+    Reminder,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, Debug, Clone)]
+pub struct Notification {
+    pub id: Uuid,
+    pub kind: NotificationKind,
+    pub title: String,
+    pub body: String,
+    pub quest_id: Option<Uuid>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub read_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// `before` is the `next` of the previous page, `unread` leaves out the read ones
+#[derive(serde::Deserialize, Debug)]
+pub struct NotificationQuery {
+    pub before: Option<Uuid>,
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub unread: bool,
+}
+
+// `unread` counts all of the user's unread notifications, not only the ones on this page
+#[derive(serde::Serialize, Debug)]
+pub struct Notifications {
+    pub items: Vec<Notification>,
+    pub unread: i64,
+    pub next: Option<Uuid>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct UnreadCount {
+    pub unread: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::FromRow, Debug)]
+pub struct NotificationPreference {
+    pub kind: NotificationKind,
+    pub enabled: bool,
+}
+
+// What the /api/events stream sends, `type` tells them apart
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveEvent {
+    Notification(Notification),
+    Points { points: i32, level: i32 },
+    Streak { current_streak: i32 },
+    // The user's place on a cached global board changed, or their points on it did
+    Leaderboard { period: LeaderboardPeriod, position: i64, rank: i64, points: i32 },
+    // The stream fell behind and skipped events, the client should reload what it shows
+    Lagged,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, sqlx::Type, Debug, Clone, Copy, PartialEq)]
+#[sqlx(type_name="reminder_kind")]
+#[sqlx(rename_all="snake_case")]
+#[serde(rename_all="snake_case")]
+pub enum ReminderKind {
+    Questionnaire,
+    StreakAtRisk,
+    WeeklyChallenge,
+    GoalDue,
+}
+
+// Times are in `timezone`, an IANA name like "Europe/Sofia"
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct ReminderSettings {
+    pub timezone: String,
+    pub questionnaire: bool,
+    pub questionnaire_time: chrono::NaiveTime,
+    pub streak_at_risk: bool,
+    pub streak_warning_time: chrono::NaiveTime,
+    pub weekly_challenge: bool,
+    pub goal_due: bool,
+    pub email: bool,
+}
+
+// Only the sent settings change
+#[derive(serde::Deserialize, Debug)]
+pub struct ReminderSettingsInput {
+    pub timezone: Option<String>,
+    pub questionnaire: Option<bool>,
+    pub questionnaire_time: Option<chrono::NaiveTime>,
+    pub streak_at_risk: Option<bool>,
+    pub streak_warning_time: Option<chrono::NaiveTime>,
+    pub weekly_challenge: Option<bool>,
+    pub goal_due: Option<bool>,
+    pub email: Option<bool>,
+}
+
+// A reminder that is due, `mail` is only set for users who want reminders by email too
+#[derive(Debug, Clone)]
+pub struct Reminder {
+    pub user_id: Uuid,
+    pub kind: ReminderKind,
+    pub mail: Option<String>,
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PushKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+// What PushSubscription.toJSON() gives in the browser, `expirationTime` is in milliseconds
+#[derive(serde::Deserialize, Debug)]
+pub struct PushSubscriptionInput {
+    pub endpoint: String,
+    #[serde(rename = "expirationTime")]
+    pub expiration_time: Option<i64>,
+    pub keys: PushKeys,
+}
+
+#[derive(serde::Serialize, sqlx::FromRow, Debug)]
+pub struct PushSubscription {
+    pub id: Uuid,
+    pub user_agent: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_success_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct VapidKey {
+    pub public_key: String,
+}
+
+// `keys` can be registered with /api/push/subscriptions together with `endpoint`
+#[derive(serde::Serialize, Debug)]
+pub struct MockPushSubscription {
+    pub endpoint: String,
+    pub keys: MockPushKeys,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct MockPushKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+// `body` is the encrypted payload in base64url
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct MockPushMessage {
+    pub ttl: Option<String>,
+    pub urgency: Option<String>,
+    pub content_encoding: Option<String>,
+    pub authorization: Option<String>,
+    pub body: String,
+    pub received_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct MockPushStatusInput {
+    pub status: u16,
+}
@@ -0,0 +1,76 @@
+use axum::{Json, extract::State, http::{HeaderMap, StatusCode}};
+use uuid::Uuid;
+
+use crate::data::{AchievementStatus, AppState};
+use super::levels;
+
+// Every achievement with the current value of its metric for user $1.
+// $2 is the user's level (it comes from the level curve, not the database).
+const METRICS_QUERY: &str = "SELECT a.*, m.value FROM achievements a CROSS JOIN LATERAL (SELECT CASE a.metric \
+        WHEN 'streak' THEN (SELECT GREATEST(u.current_streak, u.longest_streak) FROM users u WHERE u.id = $1) \
+        WHEN 'quests' THEN (SELECT COUNT(*) FROM user_quest uq WHERE uq.user_id = $1 AND uq.progress = 'verified') \
+        WHEN 'weekly' THEN (SELECT COUNT(*) FROM activity_events e WHERE e.user_id = $1 AND e.kind = 'weekly_completed') \
+        WHEN 'wheel' THEN (SELECT COUNT(*) FROM wheel_spins s LEFT JOIN wheel_challenges c ON c.id = s.challenge_id \
+            WHERE s.user_id = $1 AND s.state = 'completed' AND (a.category IS NULL OR c.category = a.category)) \
+        WHEN 'diary' THEN (SELECT COUNT(*) FROM diary d WHERE d.user_id = $1) \
+        WHEN 'questionnaire' THEN (SELECT COUNT(DISTINCT qs.answered_at::date) FROM questionnaire_scores qs WHERE qs.user_id = $1) \
+        WHEN 'level' THEN $2 \
+    END AS value) m";
+
+async fn user_level(state: &AppState, uid: Uuid) -> Result<i32, sqlx::Error> {
+    let points = sqlx::query_scalar!("SELECT points FROM users WHERE id = $1;", uid)
+        .fetch_one(&state.db_connection)
+        .await?;
+    Ok(levels::level_for(&state.level_curve, points).level)
+}
+
+// Unlocks the achievements the user has reached and returns the new ones, they also go to the activity feed.
+// Runs after anything that can move a metric, unlocked achievements stay unlocked.
+pub(crate) async fn evaluate(state: &AppState, uid: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    let level = user_level(state, uid).await?;
+    sqlx::query_scalar::<_, Uuid>(&format!("WITH unlocked AS (INSERT INTO user_achievements (user_id, achievement_id) \
+            SELECT $1, m.id FROM ({}) m WHERE m.value >= m.threshold ON CONFLICT DO NOTHING RETURNING achievement_id), \
+        events AS (INSERT INTO activity_events (user_id, kind, achievement_id) SELECT $1, 'achievement_unlocked', achievement_id FROM unlocked) \
+        SELECT achievement_id FROM unlocked;", METRICS_QUERY))
+        .bind(uid)
+        .bind(level as i64)
+        .fetch_all(&state.db_connection)
+        .await
+}
+
+// All achievements, unlocked or not, with how far the caller got
+pub async fn get_achievements(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<AchievementStatus>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let level = match user_level(&state, uid).await {
+        Ok(l) => l,
+        Err(sqlx::Error::RowNotFound) => return Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("achievements: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let res = sqlx::query_as::<_, AchievementStatus>(&format!("SELECT m.id, m.code, m.name, m.description, m.metric, m.category, m.threshold, \
+            CASE WHEN ua.unlocked_at IS NULL THEN LEAST(m.value, m.threshold) ELSE m.threshold END::integer AS progress, \
+            ua.unlocked_at IS NOT NULL AS unlocked, ua.unlocked_at \
+        FROM ({}) m LEFT JOIN user_achievements ua ON ua.achievement_id = m.id AND ua.user_id = $1 \
+        ORDER BY m.position, m.code;", METRICS_QUERY))
+        .bind(uid)
+        .bind(level as i64)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(a) => Ok(Json(a)),
+        Err(e) => {
+            eprintln!("achievements: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
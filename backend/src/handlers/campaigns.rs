@@ -0,0 +1,165 @@
+use axum::{Json, extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use uuid::Uuid;
+
+use crate::data::{AppState, Campaign, CampaignDetails, CampaignInput, CampaignLeaderboard, CampaignStanding, Quest};
+
+const CAMPAIGN_LEADERBOARD_SIZE: i64 = 10;
+
+// Lists the running campaigns together with their quests
+pub async fn list_campaigns(State(state): State<AppState>) -> Result<Json<Vec<CampaignDetails>>, StatusCode> {
+    let campaigns = sqlx::query_as!(Campaign, "SELECT * FROM campaigns WHERE NOW() BETWEEN starts_at AND ends_at ORDER BY ends_at;")
+        .fetch_all(&state.db_connection)
+        .await;
+    let campaigns = match campaigns {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("campaigns: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let mut res = Vec::new();
+    for campaign in campaigns {
+        let quests = sqlx::query_as::<_, Quest>("SELECT q.* FROM quests q JOIN campaign_quests cq ON cq.quest_id = q.id WHERE cq.campaign_id = $1;")
+            .bind(campaign.id)
+            .fetch_all(&state.db_connection)
+            .await;
+        match quests {
+            Ok(quests) => res.push(CampaignDetails { campaign, quests }),
+            Err(e) => {
+                eprintln!("campaigns: {:?}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    Ok(Json(res))
+}
+
+// Ranks users by the points they earned from the campaign's quests while it was running.
+// Same privacy rules as the main leaderboard.
+pub async fn campaign_leaderboard(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> Result<Json<CampaignLeaderboard>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    // The top of the board and the caller's row, which comes last when it's below the top
+    let standings = sqlx::query_as!(CampaignStanding, r#"SELECT name AS "name!", avatar, points AS "points!", rank AS "rank!", is_me AS "is_me!" FROM (
+            SELECT public_name(u) AS name, public_avatar(u) AS avatar, SUM(uq.points_awarded) AS points, u.id = $3 AS is_me,
+                RANK() OVER (ORDER BY SUM(uq.points_awarded) DESC) AS rank,
+                ROW_NUMBER() OVER (ORDER BY SUM(uq.points_awarded) DESC, u.name COLLATE "C") AS position
+            FROM user_quest uq JOIN users u ON u.id = uq.user_id
+            WHERE uq.campaign_id = $1 AND uq.progress = 'verified' AND on_public_leaderboard(u)
+            GROUP BY u.id) s
+        WHERE position <= $2 OR is_me ORDER BY position;"#, id, CAMPAIGN_LEADERBOARD_SIZE, uid)
+        .fetch_all(&state.db_connection)
+        .await;
+    match standings {
+        Ok(mut entries) => {
+            let me = entries.iter().find(|s| s.is_me).cloned();
+            entries.truncate(CAMPAIGN_LEADERBOARD_SIZE as usize);
+            Ok(Json(CampaignLeaderboard { entries, me }))
+        }
+        Err(e) => {
+            eprintln!("campaign leaderboard: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn admin_campaigns(State(state): State<AppState>) -> Result<Json<Vec<Campaign>>, StatusCode> {
+    let res = sqlx::query_as!(Campaign, "SELECT * FROM campaigns ORDER BY starts_at DESC;")
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(c) => Ok(Json(c)),
+        Err(e) => {
+            eprintln!("campaigns: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn admin_add_campaign(State(state): State<AppState>, Json(body): Json<CampaignInput>) -> Result<Json<Uuid>, StatusCode> {
+    if body.ends_at <= body.starts_at {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let res = sqlx::query_scalar!("INSERT INTO campaigns (name, description, starts_at, ends_at, points_multiplier) VALUES ($1, $2, $3, $4, $5) RETURNING id;",
+        body.name, body.description, body.starts_at, body.ends_at, body.points_multiplier.unwrap_or(1.0))
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(id) => Ok(Json(id)),
+        Err(e) => {
+            eprintln!("campaign: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn admin_edit_campaign(Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<CampaignInput>) -> StatusCode {
+    if body.ends_at <= body.starts_at {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let res = sqlx::query!("UPDATE campaigns SET name = $1, description = $2, starts_at = $3, ends_at = $4, points_multiplier = $5 WHERE id = $6;",
+        body.name, body.description, body.starts_at, body.ends_at, body.points_multiplier.unwrap_or(1.0), id)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("campaign: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn admin_delete_campaign(Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let res = sqlx::query!("DELETE FROM campaigns WHERE id = $1;", id)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("campaign: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn admin_add_campaign_quest(Path((id, qid)): Path<(Uuid, Uuid)>, State(state): State<AppState>) -> StatusCode {
+    let res = sqlx::query!("INSERT INTO campaign_quests (campaign_id, quest_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;", id, qid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            if e.as_database_error().is_some_and(|e| e.is_foreign_key_violation()) {
+                StatusCode::NOT_FOUND
+            } else {
+                eprintln!("campaign quest: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+pub async fn admin_remove_campaign_quest(Path((id, qid)): Path<(Uuid, Uuid)>, State(state): State<AppState>) -> StatusCode {
+    let res = sqlx::query!("DELETE FROM campaign_quests WHERE campaign_id = $1 AND quest_id = $2;", id, qid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("campaign quest: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
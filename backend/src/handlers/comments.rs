@@ -0,0 +1,207 @@
+use axum::{Json, extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use uuid::Uuid;
+
+use crate::data::{AppState, Comment, CommentInput, CommentReport, FeedAction, ReportInput, ResolveReportInput};
+use super::feed;
+
+const COMMENT_LENGTH: std::ops::RangeInclusive<usize> = 1..=280;
+const REASON_LENGTH: usize = 255;
+// Comments a user can write per hour, across all events
+const COMMENTS_PER_HOUR: i64 = 20;
+const MAX_COMMENTS: i64 = 200;
+
+// The profanity filter hook, content with a word from the list is rejected
+fn is_clean(state: &AppState, content: &str) -> bool {
+    state.profanity.is_empty() || content.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .all(|w| !state.profanity.contains(w))
+}
+
+// Comments on an event, oldest first. Comments of banned users and of users blocked either way aren't shown.
+pub async fn comments(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> Result<Json<Vec<Comment>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match feed::can_see(&state, uid, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("comments: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let res = sqlx::query_as!(Comment, r#"SELECT c.id, public_name(u) AS "name!", public_avatar(u) AS avatar, c.content, c.user_id = $2 AS "is_mine!",
+            (c.user_id = $2 OR e.user_id = $2) AS "can_delete!", c.created_at
+        FROM event_comments c JOIN users u ON u.id = c.user_id JOIN activity_events e ON e.id = c.event_id
+        WHERE c.event_id = $1 AND NOT u.banned AND NOT EXISTS (SELECT 1 FROM friendships f WHERE f.status = 'blocked'
+            AND ((f.requester_id = $2 AND f.addressee_id = c.user_id) OR (f.requester_id = c.user_id AND f.addressee_id = $2)))
+        ORDER BY c.created_at, c.id LIMIT $3;"#, id, uid, MAX_COMMENTS)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(c) => Ok(Json(c)),
+        Err(e) => {
+            eprintln!("comments: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Comments on an event the caller can see and returns the comment's id.
+// Too many comments in the last hour give 429, comments the profanity filter rejects 422.
+pub async fn add_comment(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<CommentInput>) -> Result<Json<Uuid>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let content = body.content.trim();
+    if !COMMENT_LENGTH.contains(&content.chars().count()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if !is_clean(&state, content) {
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    match comment(&state, uid, id, content).await {
+        Ok(res) => res.map(Json),
+        Err(e) => {
+            eprintln!("add comment: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn comment(state: &AppState, uid: Uuid, id: Uuid, content: &str) -> Result<Result<Uuid, StatusCode>, sqlx::Error> {
+    if !feed::can_see(state, uid, id).await? {
+        return Ok(Err(StatusCode::NOT_FOUND));
+    }
+    let mut tx = state.db_connection.begin().await?;
+    if !feed::rate_limit(&mut tx, uid, FeedAction::Comment, COMMENTS_PER_HOUR, "1 hour").await? {
+        return Ok(Err(StatusCode::TOO_MANY_REQUESTS));
+    }
+
+    let id = sqlx::query_scalar!("INSERT INTO event_comments (event_id, user_id, content) VALUES ($1, $2, $3) RETURNING id;", id, uid, content)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Ok(id))
+}
+
+// Comments can be deleted by whoever wrote them and by the author of the event they're on
+pub async fn delete_comment(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("DELETE FROM event_comments c USING activity_events e WHERE c.id = $1 AND e.id = c.event_id AND (c.user_id = $2 OR e.user_id = $2);", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("delete comment: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Reports someone else's comment to the admins, once per comment (409)
+pub async fn report_comment(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<ReportInput>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let reason = body.reason.as_deref().unwrap_or("").trim();
+    if reason.chars().count() > REASON_LENGTH {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    match report(&state, uid, id, reason).await {
+        Ok(status) => status,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => StatusCode::CONFLICT,
+        Err(e) => {
+            eprintln!("report comment: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn report(state: &AppState, uid: Uuid, id: Uuid, reason: &str) -> Result<StatusCode, sqlx::Error> {
+    let c = sqlx::query!("SELECT event_id, user_id FROM event_comments WHERE id = $1;", id)
+        .fetch_optional(&state.db_connection)
+        .await?;
+    let c = match c {
+        Some(c) if c.user_id == uid => return Ok(StatusCode::BAD_REQUEST),
+        Some(c) => c,
+        None => return Ok(StatusCode::NOT_FOUND),
+    };
+    if !feed::can_see(state, uid, c.event_id).await? {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+
+    sqlx::query!("INSERT INTO comment_reports (comment_id, reporter_id, reason) VALUES ($1, $2, $3);", id, uid, reason)
+        .execute(&state.db_connection)
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+// Open reports, oldest first
+pub async fn admin_reports(State(state): State<AppState>) -> Result<Json<Vec<CommentReport>>, StatusCode> {
+    let res = sqlx::query_as!(CommentReport, "SELECT r.id, r.comment_id, c.content, a.name AS author, u.name AS reporter, r.reason, r.created_at
+        FROM comment_reports r JOIN event_comments c ON c.id = r.comment_id JOIN users a ON a.id = c.user_id JOIN users u ON u.id = r.reporter_id
+        WHERE r.resolved_at IS NULL ORDER BY r.created_at;")
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) => Ok(Json(r)),
+        Err(e) => {
+            eprintln!("admin reports: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Resolves every open report of the reported comment, removing the comment takes its reports with it
+pub async fn admin_resolve_report(Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<ResolveReportInput>) -> StatusCode {
+    let res = if body.remove_comment {
+        sqlx::query!("DELETE FROM event_comments WHERE id = (SELECT comment_id FROM comment_reports WHERE id = $1);", id)
+            .execute(&state.db_connection)
+            .await
+    } else {
+        sqlx::query!("UPDATE comment_reports SET resolved_at = NOW()
+            WHERE comment_id = (SELECT comment_id FROM comment_reports WHERE id = $1) AND resolved_at IS NULL;", id)
+            .execute(&state.db_connection)
+            .await
+    };
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("resolve report: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::convert::Infallible;
+
+use axum::{extract::State, http::{HeaderMap, StatusCode}, response::sse::{Event, KeepAlive, Sse}};
+use tokio_stream::{Stream, StreamExt, wrappers::{BroadcastStream, errors::BroadcastStreamRecvError}};
+use uuid::Uuid;
+
+use crate::data::{AppState, LiveEvent};
+
+// Events that weren't sent yet when a stream falls this far behind are skipped
+pub(crate) const HUB_CAPACITY: usize = 1024;
+
+// Sends the event to the user's open streams, nothing happens when they have none
+pub(crate) fn publish(state: &AppState, uid: Uuid, event: LiveEvent) {
+    let _ = state.events.sender.send((uid, event));
+}
+
+// Server-Sent Events with the caller's notifications, point, streak and leaderboard changes as JSON
+pub async fn events(headers: HeaderMap, State(state): State<AppState>) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let exists = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) AS "exists!";"#, uid)
+        .fetch_one(&state.db_connection)
+        .await;
+    match exists {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("events: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let stream = BroadcastStream::new(state.events.sender.subscribe()).filter_map(move |e| {
+        let event = match e {
+            Ok((user, event)) if user == uid => event,
+            Ok(_) => return None,
+            Err(BroadcastStreamRecvError::Lagged(_)) => LiveEvent::Lagged,
+        };
+        match Event::default().json_data(event) {
+            Ok(e) => Some(Ok(e)),
+            Err(e) => {
+                eprintln!("events: {:?}", e);
+                None
+            }
+        }
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,174 @@
+use axum::{Json, extract::{Path, Query, State}, http::{HeaderMap, StatusCode}};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::data::{AppState, Feed, FeedAction, FeedItem, FeedQuery};
+use super::lock_user;
+
+const DEFAULT_FEED_SIZE: i64 = 20;
+const MAX_FEED_SIZE: i64 = 50;
+
+// Whose events the user $1 sees: their own, their friends' and those of fellow group members.
+// Users who only show themselves to friends aren't in their groups' feeds, blocked users aren't in any.
+const AUTHORS_QUERY: &str = "authors AS ( \
+        SELECT $1::uuid AS id \
+        UNION SELECT CASE WHEN f.requester_id = $1 THEN f.addressee_id ELSE f.requester_id END FROM friendships f \
+            WHERE f.status = 'accepted' AND (f.requester_id = $1 OR f.addressee_id = $1) \
+        UNION SELECT b.user_id FROM group_members a JOIN group_members b ON b.group_id = a.group_id JOIN users u ON u.id = b.user_id \
+            WHERE a.user_id = $1 AND NOT u.friends_only AND NOT EXISTS (SELECT 1 FROM friendships f WHERE f.status = 'blocked' \
+                AND ((f.requester_id = $1 AND f.addressee_id = b.user_id) OR (f.requester_id = b.user_id AND f.addressee_id = $1))))";
+
+// Counts the action towards the user's limit of `limit` per `window` (an interval like '1 hour'), false when
+// they already reached it. The user's row stays locked until the transaction ends, so concurrent requests count one after another.
+pub(crate) async fn rate_limit(tx: &mut PgConnection, uid: Uuid, action: FeedAction, limit: i64, window: &str) -> Result<bool, sqlx::Error> {
+    lock_user(tx, uid).await?;
+    let recent: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM rate_limit_log WHERE user_id = $1 AND action = $2 AND created_at > NOW() - $3::interval;")
+        .bind(uid)
+        .bind(action)
+        .bind(window)
+        .fetch_one(&mut *tx)
+        .await?;
+    if recent >= limit {
+        return Ok(false);
+    }
+    sqlx::query("INSERT INTO rate_limit_log (user_id, action) VALUES ($1, $2);")
+        .bind(uid)
+        .bind(action)
+        .execute(&mut *tx)
+        .await?;
+    Ok(true)
+}
+
+// A verified completion, the weekly quest gets its own kind
+pub(crate) async fn quest_completed(tx: &mut PgConnection, uid: Uuid, quest_id: Uuid, user_quest_id: Uuid, weekly: bool) -> Result<(), sqlx::Error> {
+    sqlx::query!("INSERT INTO activity_events (user_id, kind, quest_id, user_quest_id)
+        VALUES ($1, (CASE WHEN $4 THEN 'weekly_completed' ELSE 'quest_completed' END)::activity_event_kind, $2, $3);",
+        uid, quest_id, user_quest_id, weekly)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn streak_milestone(tx: &mut PgConnection, uid: Uuid, streak: i32) -> Result<(), sqlx::Error> {
+    sqlx::query!("INSERT INTO activity_events (user_id, kind, streak) VALUES ($1, 'streak_milestone', $2);", uid, streak)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+// The caller's events and those of their friends and fellow group members, newest first. Banned users' events aren't shown.
+pub async fn feed(headers: HeaderMap, State(state): State<AppState>, Query(query): Query<FeedQuery>) -> Result<Json<Feed>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_FEED_SIZE);
+    if !(1..=MAX_FEED_SIZE).contains(&limit) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let res = sqlx::query_as::<_, FeedItem>(&format!("WITH {} \
+        SELECT e.id, e.kind, public_name(u) AS name, public_avatar(u) AS avatar, e.user_id = $1 AS is_mine, q.name AS quest_name, e.user_quest_id, \
+            a.name AS achievement_name, e.streak, e.hidden, \
+            (SELECT COUNT(*) FROM event_reactions r WHERE r.event_id = e.id) AS reactions, \
+            (SELECT COUNT(*) FROM event_comments c JOIN users cu ON cu.id = c.user_id WHERE c.event_id = e.id AND NOT cu.banned) AS comments, e.created_at \
+        FROM activity_events e JOIN authors ON authors.id = e.user_id JOIN users u ON u.id = e.user_id \
+        LEFT JOIN quests q ON q.id = e.quest_id LEFT JOIN achievements a ON a.id = e.achievement_id \
+        WHERE NOT u.banned AND (e.user_id = $1 OR NOT e.hidden) \
+            AND NOT EXISTS (SELECT 1 FROM feed_hidden h WHERE h.user_id = $1 AND h.event_id = e.id) \
+            AND ($2::uuid IS NULL OR (e.created_at, e.id) < (SELECT b.created_at, b.id FROM activity_events b WHERE b.id = $2)) \
+        ORDER BY e.created_at DESC, e.id DESC LIMIT $3;", AUTHORS_QUERY))
+        .bind(uid)
+        .bind(query.before)
+        .bind(limit)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(items) => {
+            let next = if items.len() as i64 == limit { items.last().map(|i| i.id) } else { None };
+            Ok(Json(Feed { items, next }))
+        }
+        Err(e) => {
+            eprintln!("feed: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Whether the user could see the event in their feed, even if they hid it from there. Reactions and comments need this.
+pub(crate) async fn can_see(state: &AppState, uid: Uuid, event_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>(&format!("WITH {} SELECT EXISTS(SELECT 1 FROM activity_events e JOIN authors ON authors.id = e.user_id \
+        JOIN users u ON u.id = e.user_id WHERE e.id = $2 AND NOT u.banned AND (e.user_id = $1 OR NOT e.hidden));", AUTHORS_QUERY))
+        .bind(uid)
+        .bind(event_id)
+        .fetch_one(&state.db_connection)
+        .await
+}
+
+// Hiding one of the caller's own events hides it from everyone, anyone else's only from the caller's feed
+pub async fn hide_event(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match set_hidden(&state, uid, id, true).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("hide event: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn unhide_event(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match set_hidden(&state, uid, id, false).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("unhide event: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn set_hidden(state: &AppState, uid: Uuid, id: Uuid, hidden: bool) -> Result<StatusCode, sqlx::Error> {
+    let author = sqlx::query_scalar!("SELECT user_id FROM activity_events WHERE id = $1;", id)
+        .fetch_optional(&state.db_connection)
+        .await?;
+    match author {
+        None => return Ok(StatusCode::NOT_FOUND),
+        Some(author) if author == uid => {
+            sqlx::query!("UPDATE activity_events SET hidden = $2 WHERE id = $1;", id, hidden)
+                .execute(&state.db_connection)
+                .await?;
+        }
+        Some(_) if hidden => {
+            sqlx::query!("INSERT INTO feed_hidden (user_id, event_id) VALUES ($1, $2) ON CONFLICT DO NOTHING;", uid, id)
+                .execute(&state.db_connection)
+                .await?;
+        }
+        Some(_) => {
+            sqlx::query!("DELETE FROM feed_hidden WHERE user_id = $1 AND event_id = $2;", uid, id)
+                .execute(&state.db_connection)
+                .await?;
+        }
+    }
+    Ok(StatusCode::OK)
+}
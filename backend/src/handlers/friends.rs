@@ -0,0 +1,240 @@
+use axum::{Json, extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::data::{AppState, BlockedUser, Friend, FriendRequest, FriendRequestInput};
+
+// Accepted friends that aren't banned. The streak counts as still going while freezes can cover the missed days, like in get_streak.
+pub async fn list_friends(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<Friend>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let today = Utc::now().date_naive();
+
+    let res = sqlx::query_as!(Friend, r#"SELECT f.id, public_name(u) AS "name!", public_avatar(u) AS avatar, u.points, f.updated_at AS since,
+            CASE WHEN $2 - u.last_active <= 1 + u.streak_freezes THEN u.current_streak ELSE 0 END AS "current_streak!"
+        FROM friendships f JOIN users u ON u.id = CASE WHEN f.requester_id = $1 THEN f.addressee_id ELSE f.requester_id END
+        WHERE (f.requester_id = $1 OR f.addressee_id = $1) AND f.status = 'accepted' AND NOT u.banned
+        ORDER BY u.points DESC, public_name(u);"#, uid, today)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(f) => Ok(Json(f)),
+        Err(e) => {
+            eprintln!("friends: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Pending requests the caller sent or got, newest first
+pub async fn friend_requests(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<FriendRequest>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as!(FriendRequest, r#"SELECT f.id, public_name(u) AS "name!", public_avatar(u) AS avatar, f.addressee_id = $1 AS "incoming!", f.created_at
+        FROM friendships f JOIN users u ON u.id = CASE WHEN f.requester_id = $1 THEN f.addressee_id ELSE f.requester_id END
+        WHERE (f.requester_id = $1 OR f.addressee_id = $1) AND f.status = 'pending' AND NOT u.banned
+        ORDER BY f.created_at DESC;"#, uid)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) => Ok(Json(r)),
+        Err(e) => {
+            eprintln!("friend requests: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Users the caller blocked
+pub async fn blocked_users(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<BlockedUser>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as!(BlockedUser, r#"SELECT f.id, public_name(u) AS "name!", f.updated_at AS blocked_at FROM friendships f JOIN users u ON u.id = f.addressee_id
+        WHERE f.requester_id = $1 AND f.status = 'blocked' ORDER BY f.updated_at DESC;"#, uid)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(b) => Ok(Json(b)),
+        Err(e) => {
+            eprintln!("blocked users: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Sends a friend request by the name other users see (display name or alias) and returns the friendship's id.
+// A request to someone who already sent one to the caller accepts theirs. Banned users and users who blocked
+// the caller look like they don't exist (404), an alias more than one user has gives 409.
+pub async fn send_friend_request(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<FriendRequestInput>) -> Result<Json<Uuid>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match send_tx(&state, uid, body.name.trim()).await {
+        Ok(res) => res.map(Json),
+        // Both users sent a request at the same time
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(StatusCode::CONFLICT),
+        Err(e) => {
+            eprintln!("send friend request: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn send_tx(state: &AppState, uid: Uuid, name: &str) -> Result<Result<Uuid, StatusCode>, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let target = sqlx::query_scalar!("SELECT id FROM users u WHERE lower(public_name(u)) = lower($1) AND NOT banned LIMIT 2;", name)
+        .fetch_all(&mut *tx)
+        .await?;
+    let target = match target[..] {
+        [t] if t == uid => return Ok(Err(StatusCode::BAD_REQUEST)),
+        [t] => t,
+        [] => return Ok(Err(StatusCode::NOT_FOUND)),
+        _ => return Ok(Err(StatusCode::CONFLICT)),
+    };
+
+    let existing = sqlx::query!(r#"SELECT id, requester_id, status::text AS "status!" FROM friendships
+        WHERE (requester_id = $1 AND addressee_id = $2) OR (requester_id = $2 AND addressee_id = $1) FOR UPDATE;"#, uid, target)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(existing) = existing else {
+        let id = sqlx::query_scalar!("INSERT INTO friendships (requester_id, addressee_id) VALUES ($1, $2) RETURNING id;", uid, target)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(Ok(id));
+    };
+
+    match (existing.status.as_str(), existing.requester_id == uid) {
+        ("pending", false) => {
+            sqlx::query!("UPDATE friendships SET status = 'accepted', updated_at = NOW() WHERE id = $1;", existing.id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(Ok(existing.id))
+        }
+        ("blocked", false) => Ok(Err(StatusCode::NOT_FOUND)),
+        _ => Ok(Err(StatusCode::CONFLICT)),
+    }
+}
+
+pub async fn accept_friend_request(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("UPDATE friendships SET status = 'accepted', updated_at = NOW() WHERE id = $1 AND addressee_id = $2 AND status = 'pending';", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("accept friend request: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn decline_friend_request(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("DELETE FROM friendships WHERE id = $1 AND addressee_id = $2 AND status = 'pending';", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("decline friend request: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Removes a friend, cancels a sent request or lifts a block the caller made
+pub async fn remove_friend(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("DELETE FROM friendships WHERE id = $1 AND (requester_id = $2 OR (addressee_id = $2 AND status <> 'blocked'));", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("remove friend: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Blocks the other user of a friendship or request. They can't send the caller requests until the block is removed.
+pub async fn block_user(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("UPDATE friendships SET status = 'blocked', updated_at = NOW(), requester_id = $2,
+            addressee_id = CASE WHEN requester_id = $2 THEN addressee_id ELSE requester_id END
+        WHERE id = $1 AND (requester_id = $2 OR addressee_id = $2) AND status <> 'blocked';", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("block user: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -0,0 +1,646 @@
+use axum::{Json, extract::{Path, Query, State}, http::{HeaderMap, StatusCode}};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::data::{AppState, GroupAggregate, GroupDetails, GroupEditInput, GroupFeedItem, GroupFeedQuery, GroupInput, GroupJoinInput,
+    GroupMember, GroupQuest, GroupQuestInput, GroupRole, GroupRoleInput, GroupScoreQuery, GroupStanding, GroupSummary};
+use super::{AfterCommit, award_points, leaderboard};
+
+const MAX_GROUP_MEMBERS: i64 = 100;
+const MAX_GROUP_QUEST_BONUS: i32 = 100;
+const GROUP_NAME_LENGTH: std::ops::RangeInclusive<usize> = 3..=50;
+const GROUP_DESCRIPTION_LENGTH: usize = 255;
+const DEFAULT_FEED_SIZE: i64 = 20;
+const MAX_FEED_SIZE: i64 = 50;
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+
+// The caller's groups, or only group $2 when it's set. Banned users don't count as members.
+const SUMMARY_QUERY: &str = "SELECT g.id, g.name, g.description, m.role, \
+        (SELECT COUNT(*) FROM group_members c JOIN users u ON u.id = c.user_id WHERE c.group_id = g.id AND NOT u.banned) AS members, \
+        CASE WHEN m.role IN ('owner', 'admin') THEN g.invite_code END AS invite_code \
+    FROM group_members m JOIN groups g ON g.id = m.group_id \
+    WHERE m.user_id = $1 AND ($2::uuid IS NULL OR g.id = $2) ORDER BY g.name;";
+
+// Members of the group quest `gq` who completed its quest after it was set and before the deadline
+const CONTRIBUTORS_QUERY: &str = "SELECT DISTINCT m.user_id FROM group_members m JOIN users u ON u.id = m.user_id \
+    JOIN user_quest uq ON uq.user_id = m.user_id \
+    WHERE m.group_id = gq.group_id AND NOT u.banned AND uq.quest_id = gq.quest_id AND uq.progress = 'verified' \
+        AND uq.completed_at >= gq.created_at::date AND (gq.deadline IS NULL OR uq.completed_at <= gq.deadline::date)";
+
+async fn role_in(state: &AppState, gid: Uuid, uid: Uuid) -> Result<Option<GroupRole>, sqlx::Error> {
+    sqlx::query_scalar::<_, GroupRole>("SELECT role FROM group_members WHERE group_id = $1 AND user_id = $2;")
+        .bind(gid)
+        .bind(uid)
+        .fetch_optional(&state.db_connection)
+        .await
+}
+
+fn can_manage(role: GroupRole) -> bool {
+    matches!(role, GroupRole::Owner | GroupRole::Admin)
+}
+
+fn valid_details(name: Option<&str>, description: Option<&str>) -> bool {
+    name.is_none_or(|n| GROUP_NAME_LENGTH.contains(&n.chars().count()))
+        && description.is_none_or(|d| d.chars().count() <= GROUP_DESCRIPTION_LENGTH)
+}
+
+// Creates a group with the caller as its owner and returns its id
+pub async fn create_group(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<GroupInput>) -> Result<Json<Uuid>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let name = body.name.trim();
+    let description = body.description.as_deref().map(str::trim).unwrap_or("");
+    if !valid_details(Some(name), Some(description)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match create_tx(&state, uid, name, description).await {
+        Ok(id) => Ok(Json(id)),
+        Err(e) => {
+            eprintln!("create group: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn create_tx(state: &AppState, uid: Uuid, name: &str, description: &str) -> Result<Uuid, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let id = sqlx::query_scalar!("INSERT INTO groups (name, description) VALUES ($1, $2) RETURNING id;", name, description)
+        .fetch_one(&mut *tx)
+        .await?;
+    sqlx::query!("INSERT INTO group_members (group_id, user_id, role) VALUES ($1, $2, 'owner');", id, uid)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("INSERT INTO group_events (group_id, user_id, kind) VALUES ($1, $2, 'joined');", id, uid)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+pub async fn my_groups(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<GroupSummary>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as::<_, GroupSummary>(SUMMARY_QUERY)
+        .bind(uid)
+        .bind(None::<Uuid>)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(g) => Ok(Json(g)),
+        Err(e) => {
+            eprintln!("groups: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// The group with its members ranked by the points they earned in the period. Only members can see it.
+pub async fn group_details(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, Query(query): Query<GroupScoreQuery>) -> Result<Json<GroupDetails>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match details(&state, id, uid, &query).await {
+        Ok(Some(d)) => Ok(Json(d)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("group: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn details(state: &AppState, gid: Uuid, uid: Uuid, query: &GroupScoreQuery) -> Result<Option<GroupDetails>, sqlx::Error> {
+    let group = sqlx::query_as::<_, GroupSummary>(SUMMARY_QUERY)
+        .bind(uid)
+        .bind(gid)
+        .fetch_optional(&state.db_connection)
+        .await?;
+    let Some(group) = group else { return Ok(None) };
+
+    let member_list = sqlx::query_as::<_, GroupMember>("SELECT m.id, public_name(u) AS name, public_avatar(u) AS avatar, m.role, \
+            COALESCE((SELECT SUM(h.points) FROM points_history h WHERE h.user_id = u.id AND ($2::timestamptz IS NULL OR h.awarded_at >= $2)), 0)::integer AS points, \
+            m.joined_at, u.id = $3 AS is_me \
+        FROM group_members m JOIN users u ON u.id = m.user_id \
+        WHERE m.group_id = $1 AND NOT u.banned ORDER BY points DESC, m.joined_at;")
+        .bind(gid)
+        .bind(leaderboard::period_start(query.period))
+        .bind(uid)
+        .fetch_all(&state.db_connection)
+        .await?;
+    let total: f64 = member_list.iter().map(|m| m.points as f64).sum();
+    let score = match query.aggregate {
+        GroupAggregate::Sum => total,
+        GroupAggregate::Avg if member_list.is_empty() => 0.0,
+        GroupAggregate::Avg => total / member_list.len() as f64,
+    };
+    Ok(Some(GroupDetails { group, period: query.period, aggregate: query.aggregate, score, member_list }))
+}
+
+// Owners and admins can rename the group and change its description
+pub async fn edit_group(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<GroupEditInput>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let name = body.name.as_deref().map(str::trim);
+    let description = body.description.as_deref().map(str::trim);
+    if !valid_details(name, description) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    match role_in(&state, id, uid).await {
+        Ok(Some(role)) if can_manage(role) => {}
+        Ok(Some(_)) => return StatusCode::FORBIDDEN,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => {
+            eprintln!("edit group: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+    let res = sqlx::query!("UPDATE groups SET name = COALESCE($2, name), description = COALESCE($3, description) WHERE id = $1;", id, name, description)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("edit group: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn delete_group(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match role_in(&state, id, uid).await {
+        Ok(Some(GroupRole::Owner)) => {}
+        Ok(Some(_)) => return StatusCode::FORBIDDEN,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => {
+            eprintln!("delete group: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+    let res = sqlx::query!("DELETE FROM groups WHERE id = $1;", id)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("delete group: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Joins the group with the invite code and returns its id. Full groups and groups the caller is already in give 409.
+pub async fn join_group(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<GroupJoinInput>) -> Result<Json<Uuid>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match join_tx(&state, uid, &body.code.trim().to_uppercase()).await {
+        Ok(res) => res.map(Json),
+        Err(e) => {
+            eprintln!("join group: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn join_tx(state: &AppState, uid: Uuid, code: &str) -> Result<Result<Uuid, StatusCode>, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    // Locking the group keeps concurrent joins from going over the member limit
+    let gid = sqlx::query_scalar!("SELECT id FROM groups WHERE invite_code = $1 FOR UPDATE;", code)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(gid) = gid else { return Ok(Err(StatusCode::NOT_FOUND)) };
+
+    let members = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM group_members WHERE group_id = $1;"#, gid)
+        .fetch_one(&mut *tx)
+        .await?;
+    if members >= MAX_GROUP_MEMBERS {
+        return Ok(Err(StatusCode::CONFLICT));
+    }
+    let joined = sqlx::query_scalar!("INSERT INTO group_members (group_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING RETURNING id;", gid, uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if joined.is_none() {
+        return Ok(Err(StatusCode::CONFLICT));
+    }
+    sqlx::query!("INSERT INTO group_events (group_id, user_id, kind) VALUES ($1, $2, 'joined');", gid, uid)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Ok(gid))
+}
+
+// Leaving as the owner hands the group over, see `hand_over_groups`
+pub async fn leave_group(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match leave_tx(&state, id, uid).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("leave group: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn leave_tx(state: &AppState, gid: Uuid, uid: Uuid) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let left = sqlx::query!("INSERT INTO group_events (group_id, user_id, kind) SELECT group_id, user_id, 'left' FROM group_members WHERE group_id = $1 AND user_id = $2;", gid, uid)
+        .execute(&mut *tx)
+        .await?;
+    if left.rows_affected() == 0 {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    hand_over_groups(&mut tx, uid, Some(gid)).await?;
+    sqlx::query!("DELETE FROM group_members WHERE group_id = $1 AND user_id = $2;", gid, uid)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(StatusCode::OK)
+}
+
+// Passes the groups `uid` owns (only `group` when set) on to their longest-standing admin, or member if there's no admin.
+// The owner's membership is removed and groups without anyone left are deleted.
+pub(crate) async fn hand_over_groups(tx: &mut PgConnection, uid: Uuid, group: Option<Uuid>) -> Result<(), sqlx::Error> {
+    let owned = sqlx::query_scalar!("DELETE FROM group_members WHERE user_id = $1 AND role = 'owner' AND ($2::uuid IS NULL OR group_id = $2) RETURNING group_id;", uid, group)
+        .fetch_all(&mut *tx)
+        .await?;
+    if owned.is_empty() {
+        return Ok(());
+    }
+    sqlx::query!("UPDATE group_members m SET role = 'owner' FROM (
+            SELECT DISTINCT ON (group_id) id FROM group_members WHERE group_id = ANY($1) ORDER BY group_id, role = 'admin' DESC, joined_at, id) n
+        WHERE m.id = n.id;", &owned)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM groups g WHERE g.id = ANY($1) AND NOT EXISTS (SELECT 1 FROM group_members m WHERE m.group_id = g.id);", &owned)
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// Replaces the invite code, the old one stops working
+pub async fn new_invite_code(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> Result<Json<String>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match role_in(&state, id, uid).await {
+        Ok(Some(role)) if can_manage(role) => {}
+        Ok(Some(_)) => return Err(StatusCode::FORBIDDEN),
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("invite code: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let res = sqlx::query_scalar!("UPDATE groups SET invite_code = DEFAULT WHERE id = $1 RETURNING invite_code;", id)
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(c) => Ok(Json(c)),
+        Err(e) => {
+            eprintln!("invite code: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Only the owner changes roles. Making someone the owner transfers the group, the old owner becomes an admin.
+pub async fn set_member_role(headers: HeaderMap, Path((id, member)): Path<(Uuid, Uuid)>, State(state): State<AppState>, Json(body): Json<GroupRoleInput>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match role_tx(&state, id, uid, member, body.role).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("member role: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn role_tx(state: &AppState, gid: Uuid, uid: Uuid, member: Uuid, role: GroupRole) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let owner = sqlx::query_scalar!("SELECT id FROM group_members WHERE group_id = $1 AND user_id = $2 AND role = 'owner' FOR UPDATE;", gid, uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(owner) = owner else { return Ok(StatusCode::FORBIDDEN) };
+    if owner == member {
+        return Ok(StatusCode::BAD_REQUEST);
+    }
+
+    if role == GroupRole::Owner {
+        // The old owner steps down first, there can only be one
+        sqlx::query!("UPDATE group_members SET role = 'admin' WHERE id = $1;", owner)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let res = sqlx::query("UPDATE group_members SET role = $3 WHERE id = $1 AND group_id = $2;")
+        .bind(member)
+        .bind(gid)
+        .bind(role)
+        .execute(&mut *tx)
+        .await?;
+    if res.rows_affected() == 0 {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    tx.commit().await?;
+    Ok(StatusCode::OK)
+}
+
+// Owners can remove anyone, admins only members
+pub async fn remove_member(headers: HeaderMap, Path((id, member)): Path<(Uuid, Uuid)>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let role = match role_in(&state, id, uid).await {
+        Ok(Some(role)) if can_manage(role) => role,
+        Ok(Some(_)) => return StatusCode::FORBIDDEN,
+        Ok(None) => return StatusCode::NOT_FOUND,
+        Err(e) => {
+            eprintln!("remove member: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let removable = if role == GroupRole::Owner { vec![GroupRole::Admin, GroupRole::Member] } else { vec![GroupRole::Member] };
+    let res = sqlx::query("WITH removed AS (DELETE FROM group_members WHERE id = $1 AND group_id = $2 AND role = ANY($3) RETURNING group_id, user_id) \
+        INSERT INTO group_events (group_id, user_id, kind) SELECT group_id, user_id, 'left' FROM removed;")
+        .bind(member)
+        .bind(id)
+        .bind(removable)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("remove member: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Joins, departures and completed quests of the members, newest first
+pub async fn group_feed(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, Query(query): Query<GroupFeedQuery>) -> Result<Json<Vec<GroupFeedItem>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_FEED_SIZE);
+    if !(1..=MAX_FEED_SIZE).contains(&limit) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match role_in(&state, id, uid).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("group feed: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let res = sqlx::query_as::<_, GroupFeedItem>("SELECT e.id, e.kind, public_name(u) AS name, public_avatar(u) AS avatar, q.name AS quest_name, e.created_at \
+        FROM group_events e JOIN users u ON u.id = e.user_id LEFT JOIN quests q ON q.id = e.quest_id \
+        WHERE e.group_id = $1 AND NOT u.banned \
+            AND ($2::uuid IS NULL OR (e.created_at, e.id) < (SELECT b.created_at, b.id FROM group_events b WHERE b.id = $2)) \
+        ORDER BY e.created_at DESC, e.id DESC LIMIT $3;")
+        .bind(id)
+        .bind(query.before)
+        .bind(limit)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(f) => Ok(Json(f)),
+        Err(e) => {
+            eprintln!("group feed: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Open group quests first
+pub async fn group_quests(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> Result<Json<Vec<GroupQuest>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match role_in(&state, id, uid).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("group quests: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let res = sqlx::query_as::<_, GroupQuest>(&format!("SELECT gq.id, gq.quest_id, q.name AS quest_name, gq.required_members, gq.bonus_points, \
+            gq.deadline, gq.created_at, gq.completed_at, c.completions, c.completed_by_me \
+        FROM group_quests gq JOIN quests q ON q.id = gq.quest_id \
+        CROSS JOIN LATERAL (SELECT COUNT(*) AS completions, COALESCE(bool_or(c.user_id = $2), false) AS completed_by_me FROM ({}) c) c \
+        WHERE gq.group_id = $1 ORDER BY gq.completed_at IS NOT NULL, gq.created_at DESC;", CONTRIBUTORS_QUERY))
+        .bind(id)
+        .bind(uid)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(q) => Ok(Json(q)),
+        Err(e) => {
+            eprintln!("group quests: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Owners and admins set group quests, returns the group quest's id
+pub async fn add_group_quest(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<GroupQuestInput>) -> Result<Json<Uuid>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let bonus_points = body.bonus_points.unwrap_or(0);
+    if !(1..=MAX_GROUP_MEMBERS).contains(&(body.required_members as i64))
+        || !(0..=MAX_GROUP_QUEST_BONUS).contains(&bonus_points)
+        || body.deadline.is_some_and(|d| d <= chrono::Utc::now()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match role_in(&state, id, uid).await {
+        Ok(Some(role)) if can_manage(role) => {}
+        Ok(Some(_)) => return Err(StatusCode::FORBIDDEN),
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("add group quest: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let res = sqlx::query_scalar!("INSERT INTO group_quests (group_id, quest_id, required_members, bonus_points, deadline) VALUES ($1, $2, $3, $4, $5) RETURNING id;",
+        id, body.quest_id, body.required_members, bonus_points, body.deadline)
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(id) => Ok(Json(id)),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("add group quest: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Groups ranked by the sum or average of the points their members earned in the period
+pub async fn group_leaderboard(headers: HeaderMap, State(state): State<AppState>, Query(query): Query<GroupScoreQuery>) -> Result<Json<Vec<GroupStanding>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+    if !(1..=MAX_LIMIT).contains(&limit) || offset < 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let res = sqlx::query_as::<_, GroupStanding>("WITH scores AS (SELECT g.id, g.name, COUNT(*) AS members, bool_or(m.user_id = $3) AS is_mine, \
+                (CASE WHEN $2 THEN AVG(p.points) ELSE SUM(p.points) END)::float8 AS score \
+            FROM groups g JOIN group_members m ON m.group_id = g.id JOIN users u ON u.id = m.user_id AND NOT u.banned \
+            CROSS JOIN LATERAL (SELECT COALESCE(SUM(h.points), 0) AS points FROM points_history h \
+                WHERE h.user_id = m.user_id AND ($1::timestamptz IS NULL OR h.awarded_at >= $1)) p \
+            GROUP BY g.id) \
+        SELECT s.*, RANK() OVER (ORDER BY s.score DESC) AS rank FROM scores s ORDER BY s.score DESC, s.name, s.id LIMIT $4 OFFSET $5;")
+        .bind(leaderboard::period_start(query.period))
+        .bind(query.aggregate == GroupAggregate::Avg)
+        .bind(uid)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(s) => Ok(Json(s)),
+        Err(e) => {
+            eprintln!("group leaderboard: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Called with every verified completion. Posts it to the user's group feeds and completes the open group quests
+// for the quest that now have enough members, their bonus goes to every member who took part.
+pub(crate) async fn quest_completed(tx: &mut PgConnection, state: &AppState, after: &mut AfterCommit, uid: Uuid, quest_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!("INSERT INTO group_events (group_id, user_id, kind, quest_id) SELECT group_id, $1, 'quest_completed', $2 FROM group_members WHERE user_id = $1;",
+        uid, quest_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Locked so that members finishing at the same time see each other's completions
+    let open = sqlx::query!("SELECT gq.id, gq.group_id, gq.required_members, gq.bonus_points FROM group_quests gq
+            JOIN group_members m ON m.group_id = gq.group_id AND m.user_id = $1
+        WHERE gq.quest_id = $2 AND gq.completed_at IS NULL AND (gq.deadline IS NULL OR gq.deadline > NOW())
+        ORDER BY gq.id FOR UPDATE OF gq;", uid, quest_id)
+        .fetch_all(&mut *tx)
+        .await?;
+    for gq in open {
+        let contributors = sqlx::query_scalar::<_, Uuid>(&format!("SELECT c.user_id FROM group_quests gq CROSS JOIN LATERAL ({}) c \
+            WHERE gq.id = $1 ORDER BY c.user_id;", CONTRIBUTORS_QUERY))
+            .bind(gq.id)
+            .fetch_all(&mut *tx)
+            .await?;
+        if (contributors.len() as i64) < gq.required_members as i64 {
+            continue;
+        }
+
+        sqlx::query!("UPDATE group_quests SET completed_at = NOW() WHERE id = $1;", gq.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("INSERT INTO group_events (group_id, user_id, kind, quest_id) VALUES ($1, $2, 'group_quest_completed', $3);", gq.group_id, uid, quest_id)
+            .execute(&mut *tx)
+            .await?;
+        if gq.bonus_points > 0 {
+            for member in contributors {
+                award_points(tx, state, after, member, gq.bonus_points).await?;
+            }
+        }
+    }
+    Ok(())
+}
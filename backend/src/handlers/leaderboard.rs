@@ -0,0 +1,225 @@
+use std::{ops::RangeInclusive, sync::atomic::Ordering, time::Instant};
+
+use axum::{Json, extract::{Query, State}, http::{HeaderMap, StatusCode}};
+use chrono::{DateTime, Datelike, Days, Utc};
+use uuid::Uuid;
+
+use crate::data::{AppState, Leaderboard, LeaderboardBoardMetrics, LeaderboardEntry, LeaderboardMetrics, LeaderboardPeriod, LeaderboardQuery, LeaderboardScope, LeaderboardSnapshot, LeaderboardWrites, LiveEvent, Standing};
+use super::{events, levels};
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 100;
+// How many users above and below the caller come with their rank
+const NEIGHBOURS: i64 = 2;
+// Builds of a board that points were committed during, after the last one it's served without caching it
+const BUILD_ATTEMPTS: u32 = 3;
+
+// Periods follow the calendar in UTC, weeks start on Monday
+pub(crate) fn period_start(period: LeaderboardPeriod) -> Option<DateTime<Utc>> {
+    let today = Utc::now().date_naive();
+    let start = match period {
+        LeaderboardPeriod::Week => today - Days::new(today.weekday().num_days_from_monday() as u64),
+        LeaderboardPeriod::Month => today.with_day(1)?,
+        LeaderboardPeriod::All => return None,
+    };
+    Some(start.and_hms_opt(0, 0, 0)?.and_utc())
+}
+
+// Users by the points they earned since `start`, best first. Global boards have the users on the public board
+// (see `on_public_leaderboard`), friends boards `friends_of` and their friends that are shown on leaderboards.
+// Names are compared bytewise so the order matches `ranks_before` after incremental updates.
+async fn build_snapshot(state: &AppState, start: Option<DateTime<Utc>>, friends_of: Option<Uuid>) -> Result<LeaderboardSnapshot, sqlx::Error> {
+    let standings = sqlx::query_as!(Standing, r#"SELECT u.id AS user_id, u.name, public_name(u) AS "public_name!", public_avatar(u) AS avatar,
+            COALESCE(SUM(h.points), 0)::integer AS "points!", u.points AS total_points
+        FROM users u LEFT JOIN points_history h ON h.user_id = u.id AND ($1::timestamptz IS NULL OR h.awarded_at >= $1)
+        WHERE CASE WHEN $2::uuid IS NULL THEN on_public_leaderboard(u)
+            ELSE NOT u.banned AND NOT u.is_admin AND u.show_on_leaderboard AND (u.id = $2 OR are_friends(u.id, $2)) END
+        GROUP BY u.id ORDER BY 5 DESC, u.name COLLATE "C";"#, start, friends_of)
+        .fetch_all(&state.db_connection)
+        .await?;
+    let index = standings.iter().enumerate().map(|(i, s)| (s.user_id, i)).collect();
+    Ok(LeaderboardSnapshot { built: Instant::now(), start, standings, index })
+}
+
+fn ranks_before(a: &Standing, b: &Standing) -> bool {
+    a.points > b.points || (a.points == b.points && a.name.as_bytes() < b.name.as_bytes())
+}
+
+// Moves the user to their new place, only the users they pass get shifted. Returns the places that changed hands.
+fn apply(board: &mut LeaderboardSnapshot, uid: Uuid, points: i32) -> Option<RangeInclusive<usize>> {
+    let &start = board.index.get(&uid)?;
+    let standings = &mut board.standings;
+    standings[start].points += points;
+    standings[start].total_points += points;
+
+    let mut i = start;
+    while i > 0 && ranks_before(&standings[i], &standings[i - 1]) {
+        standings.swap(i, i - 1);
+        board.index.insert(standings[i].user_id, i);
+        i -= 1;
+    }
+    while i + 1 < standings.len() && ranks_before(&standings[i + 1], &standings[i]) {
+        standings.swap(i, i + 1);
+        board.index.insert(standings[i].user_id, i);
+        i += 1;
+    }
+    board.index.insert(uid, i);
+    Some(start.min(i)..=start.max(i))
+}
+
+// Keeps the cached boards up to date with a committed change of the user's points. Users that aren't on a board yet
+// (new or just unbanned) get picked up when the board is rebuilt.
+// The user and everyone they passed get their new place over /api/events.
+pub(crate) async fn apply_points(state: &AppState, uid: Uuid, points: i32) {
+    let mut cache = state.leaderboards.lock().await;
+    for (&period, board) in cache.boards.iter_mut() {
+        let Some(moved) = apply(board, uid, points) else { continue };
+        for i in moved {
+            let s = &board.standings[i];
+            let rank = board.standings.partition_point(|o| o.points > s.points) + 1;
+            events::publish(state, s.user_id, LiveEvent::Leaderboard { period, position: i as i64 + 1, rank: rank as i64, points: s.points });
+        }
+    }
+}
+
+// For changes that take users on or off the boards or change how they are shown
+pub(crate) async fn invalidate(state: &AppState) {
+    let mut cache = state.leaderboards.lock().await;
+    cache.boards.clear();
+    state.leaderboard_writes.generation.fetch_add(1, Ordering::SeqCst);
+}
+
+// Held from before a transaction with point awards commits until its points are applied to the boards.
+// Dropping it also covers commits that failed or whose request went away.
+pub(crate) struct Committing<'a>(&'a LeaderboardWrites);
+
+impl<'a> Committing<'a> {
+    pub(crate) fn start(state: &'a AppState) -> Self {
+        state.leaderboard_writes.committing.fetch_add(1, Ordering::SeqCst);
+        Committing(&state.leaderboard_writes)
+    }
+}
+
+impl Drop for Committing<'_> {
+    fn drop(&mut self) {
+        self.0.generation.fetch_add(1, Ordering::SeqCst);
+        self.0.committing.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn entry(state: &AppState, board: &LeaderboardSnapshot, i: usize, uid: Uuid) -> LeaderboardEntry {
+    let s = &board.standings[i];
+    // Ties share the rank of the first user with the same points
+    let rank = board.standings.partition_point(|o| o.points > s.points) + 1;
+    LeaderboardEntry {
+        name: s.public_name.clone(),
+        avatar: s.avatar.clone(),
+        points: s.points,
+        level: levels::level_for(&state.level_curve, s.total_points).level,
+        rank: rank as i64,
+        position: i as i64 + 1,
+        is_me: s.user_id == uid,
+    }
+}
+
+// Ranks users by the points they earned in the period, users show up under their public name. Ties share a rank, `position` breaks them by name so pages don't overlap.
+// Global boards are served from a snapshot that is at most leaderboard_max_staleness old and kept current on point changes in between.
+pub async fn leaderboard(headers: HeaderMap, State(state): State<AppState>, Query(query): Query<LeaderboardQuery>) -> Result<Json<Leaderboard>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    if !(1..=MAX_LIMIT).contains(&query.limit.unwrap_or(DEFAULT_LIMIT)) || query.offset.unwrap_or(0) < 0 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let start = period_start(query.period);
+    // Friends boards are small and differ per user, they aren't cached
+    if query.scope == LeaderboardScope::Friends {
+        return match build_snapshot(&state, start, Some(uid)).await {
+            Ok(board) => Ok(Json(page(&state, &board, &query, uid))),
+            Err(e) => {
+                eprintln!("leaderboard: {:?}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    }
+
+    let mut cache = state.leaderboards.lock().await;
+    if let Some(board) = cache.boards.get(&query.period)
+        && board.start == start && board.built.elapsed() <= state.leaderboard_max_staleness {
+        let res = page(&state, board, &query, uid);
+        cache.hits += 1;
+        return Ok(Json(res));
+    }
+    cache.misses += 1;
+    // Built without holding the lock, so point awards don't wait for the query. The board is only cached when
+    // no points were committed during the build, those could be missing from it or get applied to it twice.
+    drop(cache);
+    let writes = &state.leaderboard_writes;
+    let mut attempt = 1;
+    loop {
+        let generation = writes.generation.load(Ordering::SeqCst);
+        let quiet = writes.committing.load(Ordering::SeqCst) == 0;
+        let board = match build_snapshot(&state, start, None).await {
+            Ok(b) => b,
+            Err(e) => {
+                eprintln!("leaderboard: {:?}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        let res = page(&state, &board, &query, uid);
+        let mut cache = state.leaderboards.lock().await;
+        if quiet && writes.committing.load(Ordering::SeqCst) == 0 && writes.generation.load(Ordering::SeqCst) == generation {
+            cache.boards.insert(query.period, board);
+            return Ok(Json(res));
+        }
+        if attempt == BUILD_ATTEMPTS {
+            return Ok(Json(res));
+        }
+        attempt += 1;
+    }
+}
+
+fn page(state: &AppState, board: &LeaderboardSnapshot, query: &LeaderboardQuery, uid: Uuid) -> Leaderboard {
+    let len = board.standings.len();
+    let offset = query.offset.unwrap_or(0) as usize;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT) as usize;
+    let entries = (offset.min(len)..offset.saturating_add(limit).min(len))
+        .map(|i| entry(state, board, i, uid))
+        .collect();
+
+    let mine = board.index.get(&uid).copied();
+    let me = mine.map(|i| entry(state, board, i, uid));
+    let around_me = match mine {
+        Some(i) => (i.saturating_sub(NEIGHBOURS as usize)..(i + NEIGHBOURS as usize + 1).min(len))
+            .map(|j| entry(state, board, j, uid))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    Leaderboard { period: query.period, scope: query.scope, total: len as i64, entries, me, around_me }
+}
+
+pub async fn admin_leaderboard_metrics(State(state): State<AppState>) -> Json<LeaderboardMetrics> {
+    let cache = state.leaderboards.lock().await;
+    let requests = cache.hits + cache.misses;
+    let boards = [LeaderboardPeriod::Week, LeaderboardPeriod::Month, LeaderboardPeriod::All].into_iter()
+        .filter_map(|period| cache.boards.get(&period).map(|b| LeaderboardBoardMetrics {
+            period,
+            age_secs: b.built.elapsed().as_secs(),
+            users: b.standings.len(),
+        }))
+        .collect();
+
+    Json(LeaderboardMetrics {
+        hits: cache.hits,
+        misses: cache.misses,
+        hit_rate: if requests == 0 { 0.0 } else { cache.hits as f64 / requests as f64 },
+        boards,
+    })
+}
@@ -0,0 +1,61 @@
+use axum::{Json, extract::State, http::{HeaderMap, StatusCode}};
+use uuid::Uuid;
+
+use crate::data::{AppState, Level, LevelCurve, LevelUp};
+
+const LEVEL_UP_HISTORY_SIZE: i64 = 20;
+
+// Points of the quests offered from a level on, the highest reached tier wins
+const QUEST_TIERS: [(i32, i32); 3] = [(10, 30), (5, 15), (1, 10)];
+
+// XP needed to get from `level` to the next one
+fn level_cost(curve: &LevelCurve, level: i32) -> i32 {
+    let cost = curve.base_xp as f64 * curve.growth.powi(level - 1);
+    (cost.round() as i32).max(1)
+}
+
+// Where a user with `points` stands on the curve, everyone starts at level 1
+pub(crate) fn level_for(curve: &LevelCurve, points: i32) -> Level {
+    let mut level = 1;
+    let mut rest = points.max(0);
+    loop {
+        let cost = level_cost(curve, level);
+        if rest < cost {
+            return Level { level, xp_into_level: rest, xp_for_next_level: cost };
+        }
+        rest -= cost;
+        level += 1;
+    }
+}
+
+// The points of the quests a user at `level` gets offered
+pub(crate) fn quest_tier(level: i32) -> i32 {
+    QUEST_TIERS.iter()
+        .find(|(from, _)| level >= *from)
+        .map(|(_, points)| *points)
+        .unwrap_or(QUEST_TIERS[QUEST_TIERS.len() - 1].1)
+}
+
+// The caller's latest level-ups, newest first
+pub async fn level_ups(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<LevelUp>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as!(LevelUp, "SELECT level, reached_at FROM level_ups WHERE user_id = $1 ORDER BY reached_at DESC, level DESC LIMIT $2;",
+        uid, LEVEL_UP_HISTORY_SIZE)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(l) => Ok(Json(l)),
+        Err(e) => {
+            eprintln!("level ups: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -1,71 +1,182 @@
 use std::str::FromStr;
 
-use axum::{Json, extract::{Path, Request, State}, http::{HeaderMap, StatusCode}, middleware::Next, response::Response};
-use chrono::{Datelike, Days, Utc};
+use axum::{Json, extract::{Path, Query, Request, State}, http::{HeaderMap, StatusCode}, middleware::Next, response::Response};
+use chrono::{Datelike, Days, NaiveDate, Utc};
 use rand::Rng;
 use regex::Regex;
 use serde::Serialize;
-use serde_json::{Value, json};
+use serde_json::Value;
 use sha2::Digest;
-use sqlx::{prelude::FromRow, query_as, query_scalar};
+use sqlx::{PgConnection, PgExecutor, Postgres, Transaction, prelude::FromRow, query_as, query_scalar};
 use uuid::Uuid;
 
-use crate::data::{self, AppState, DiaryData, DiaryInput, PersonalChallange, PersonalChallangeInput, Quest, User};
+use crate::data::{self, AppState, DiaryData, DiaryInput, GoalQuery, GoalSort, PersonalChallange, PersonalChallangeInput, PersonalChallangeUpdate, Quest, RepeatPolicy, User};
+
+pub mod achievements;
+pub mod campaigns;
+pub mod comments;
+pub mod events;
+pub mod feed;
+pub mod friends;
+pub mod groups;
+pub mod leaderboard;
+pub mod levels;
+pub mod notifications;
+pub mod peer_challenges;
+pub mod progress;
+pub mod push;
+pub mod reminders;
+pub mod reactions;
+pub mod settings;
+pub mod streaks;
+pub mod wheel;
 
 const WEEKLY_POINTS: i32 = 50;
+// Given for the first questionnaire of the day
+const QUESTIONNAIRE_POINTS: i32 = 5;
+// Goal priorities, 1 is the most important
+const GOAL_PRIORITY: std::ops::RangeInclusive<i32> = 1..=5;
 
-pub async fn request_challange(headers: HeaderMap, State(state): State<data::AppState>) -> Json<Vec<data::Quest>> {
+pub async fn request_challange(headers: HeaderMap, State(state): State<data::AppState>) -> Json<Vec<data::OfferedQuest>> {
 
     let token = headers.get("user_id").unwrap();
     let id: Uuid = token.to_str().unwrap().parse().unwrap();
-    let mut points: i32 = query_scalar!("SELECT points FROM users WHERE id = $1;", id)
+    let points: i32 = query_scalar!("SELECT points FROM users WHERE id = $1;", id)
         .fetch_one(&state.db_connection)
         .await.unwrap();
-    let level: f32 = points as f32 / 100f32;
-    let level = level.ceil() as i32;
-    if level < 5 {
-        points = 10;
-    } else if level < 10 {
-        points = 15;
-    } else {
-        points = 30;
-    }
+    let points = levels::quest_tier(levels::level_for(&state.level_curve, points).level);
 
     // Select the best matching quest: the one with the highest required_points that is <= user's points
-    // Exclude quests the user already has in user_quest (so completed/pending quests are not re-assigned)
+    // Quests the user already did are only re-offered when their repeat policy allows it.
     // Maybe change points_received <= $1 to points_received = $1 ?
-    let quest = query_as::<_, data::Quest>("SELECT * FROM quests WHERE points_received <= $1 AND id NOT IN (SELECT quest_id FROM user_quest WHERE user_id = $2) ORDER BY required_points DESC;")
-        .bind(points)
+    let quests = query_as::<_, data::OfferedQuest>(&format!("{} AND q.points_received <= $2 ORDER BY q.required_points DESC;", QUEST_HISTORY_QUERY))
         .bind(id)
+        .bind(points)
         .fetch_all(&state.db_connection)
         .await;
 
-    match quest {
-        Ok(quest) => {
-            Json(quest)
+    match quests {
+        Ok(quests) => {
+            let today = Utc::now().date_naive();
+            let mut offered = Vec::new();
+            for mut q in quests {
+                match quest_availability(&q, today) {
+                    Availability::Now => offered.push(q),
+                    Availability::From(date) => {
+                        q.available_at = Some(date);
+                        offered.push(q);
+                    }
+                    Availability::Never => {}
+                }
+            }
+            // Cooling down quests go last so the first one can always be taken right away
+            offered.sort_by_key(|q| q.available_at.is_some());
+            Json(offered)
         }
         Err(_) => {
-            Json(vec![Quest { id: Uuid::nil(), name: "".to_string(), description: "".to_string(), required_points: 0, points_received:  0}])
+            Json(vec![data::OfferedQuest { quest: Quest::default(), completions: 0, last_completed: None, available_at: None, campaign_id: None, points_multiplier: None }])
         }
     }
 }
 
-pub async fn send_challange(headers: HeaderMap, State(state): State<data::AppState>, Path(quest_id): Path<Uuid>) {
+// Every available quest with the number of times the user did it and when they last did it.
+// Denied attempts don't count, so the user can try those again.
+// Quests that belong to campaigns are only available while one of them runs, the biggest multiplier wins.
+const QUEST_HISTORY_QUERY: &str = "SELECT q.*, h.completions, h.last_completed, c.id AS campaign_id, c.points_multiplier FROM quests q \
+    CROSS JOIN LATERAL (SELECT COUNT(*) AS completions, MAX(uq.completed_at) AS last_completed FROM user_quest uq \
+        WHERE uq.quest_id = q.id AND uq.user_id = $1 AND uq.progress IS DISTINCT FROM 'denied') h \
+    LEFT JOIN LATERAL (SELECT c.id, c.points_multiplier FROM campaigns c JOIN campaign_quests cq ON cq.campaign_id = c.id \
+        WHERE cq.quest_id = q.id AND NOW() BETWEEN c.starts_at AND c.ends_at ORDER BY c.points_multiplier DESC LIMIT 1) c ON true \
+    WHERE (c.id IS NOT NULL OR NOT EXISTS (SELECT 1 FROM campaign_quests cq WHERE cq.quest_id = q.id))";
+
+pub(crate) enum Availability {
+    Now,
+    From(NaiveDate),
+    Never,
+}
+
+pub(crate) fn quest_availability(q: &data::OfferedQuest, today: NaiveDate) -> Availability {
+    if q.completions == 0 {
+        return Availability::Now;
+    }
+    if let Some(max) = q.quest.max_completions && q.completions >= max as i64 {
+        return Availability::Never;
+    }
+    let cooldown = match q.quest.repeat_policy {
+        RepeatPolicy::Once => return Availability::Never,
+        RepeatPolicy::Daily => 1,
+        RepeatPolicy::Weekly => 7,
+        RepeatPolicy::Interval => q.quest.repeat_interval_days.unwrap_or(1),
+    };
+    match q.last_completed.and_then(|d| d.checked_add_days(Days::new(cooldown as u64))) {
+        Some(date) if date > today => Availability::From(date),
+        _ => Availability::Now,
+    }
+}
+
+// Fetches a quest along with the user's history on it
+pub(crate) async fn get_quest_history(db: impl PgExecutor<'_>, uid: Uuid, qid: Uuid) -> Result<data::OfferedQuest, sqlx::Error> {
+    query_as::<_, data::OfferedQuest>(&format!("{} AND q.id = $2;", QUEST_HISTORY_QUERY))
+        .bind(uid)
+        .bind(qid)
+        .fetch_one(db)
+        .await
+}
+
+// Locks the user's row until the transaction ends, so completions of the same user run one after another
+pub(crate) async fn lock_user(tx: &mut PgConnection, uid: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE;", uid)
+        .fetch_one(tx)
+        .await?;
+    Ok(())
+}
+
+// The unique index and the repeat trigger on user_quest reject completions that are not allowed (anymore)
+pub(crate) fn is_repeat_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error().is_some_and(|e| e.is_unique_violation() || e.is_check_violation())
+}
+
+pub async fn send_challange(headers: HeaderMap, State(state): State<data::AppState>, Path(quest_id): Path<Uuid>) -> StatusCode {
     
     let token = headers.get("user_id").unwrap();
     let id: Uuid = token.to_str().unwrap().parse().unwrap();
 
-    sqlx::query("INSERT INTO user_quest (user_id, quest_id, progress, proof_path) VALUES($1, $2, $3, $4)")
+    let campaign_id = match get_quest_history(&state.db_connection, id, quest_id).await {
+        Ok(q) => {
+            if !matches!(quest_availability(&q, Utc::now().date_naive()), Availability::Now) {
+                return StatusCode::CONFLICT;
+            }
+            // Multi-step quests are completed through check-ins
+            if q.quest.required_checkins > 1 {
+                return StatusCode::BAD_REQUEST;
+            }
+            q.campaign_id
+        }
+        Err(sqlx::Error::RowNotFound) => return StatusCode::NOT_FOUND,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    let res = sqlx::query("INSERT INTO user_quest (user_id, quest_id, progress, proof_path, campaign_id) VALUES($1, $2, $3, $4, $5)")
         .bind(id)
         .bind(quest_id)
         .bind(data::Progress::Pending)
         .bind("")
+        .bind(campaign_id)
         .execute(&state.db_connection)
-        .await.unwrap();
+        .await;
+    match res {
+        Ok(_) => {}
+        Err(e) if is_repeat_violation(&e) => return StatusCode::CONFLICT,
+        Err(e) => {
+            eprintln!("send: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
 
     // Update the streak
-    let _ = update_streak(&state, &headers).await;
+    let _ = update_streak(&state, &headers, data::ActivityKind::Quest, 1).await;
 
+    StatusCode::OK
 }
 
 // Needs name, email and password in json format
@@ -171,7 +282,7 @@ pub async fn get_pending_quest(_headers: HeaderMap, State(state): State<data::Ap
     }
 }
 
-pub async fn me(headers: HeaderMap, State(state): State<data::AppState>) -> (StatusCode, Json<Option<data::User>>) {
+pub async fn me(headers: HeaderMap, State(state): State<data::AppState>) -> (StatusCode, Json<Option<data::Me>>) {
 
 
     let token = match headers.get("user_id") {
@@ -194,7 +305,11 @@ pub async fn me(headers: HeaderMap, State(state): State<data::AppState>) -> (Sta
     match user {
         Ok(mut u) => {
             u.password_hash = "".into(); // Do not return the hash to the front end
-            (StatusCode::OK, Json(Some(u)))
+            let level = levels::level_for(&state.level_curve, u.points);
+            match progress::get_user_progress(&state.db_connection, id).await {
+                Ok(quests_in_progress) => (StatusCode::OK, Json(Some(data::Me { user: u, level, quests_in_progress }))),
+                Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(None)),
+            }
         }
         Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, Json(None)),
     }
@@ -209,28 +324,62 @@ pub async fn verify_quest(State(state): State<data::AppState>, headers: HeaderMa
     let r = sqlx::query!("SELECT is_admin FROM users WHERE id = $1;", id)
         .fetch_one(&state.db_connection)
         .await.unwrap();
-    if r.is_admin == false {
+    if !r.is_admin {
         return StatusCode::UNAUTHORIZED;
     }
 
-    if body.completed {
-        let uid = sqlx::query!("UPDATE user_quest SET progress = 'verified' WHERE id = $1 RETURNING user_id;", qid)
-            .fetch_one(&state.db_connection)
-            .await.unwrap();
-        sqlx::query!("UPDATE users SET points = points + 9 WHERE id = $1;", uid.user_id)
-            .execute(&state.db_connection)
-            .await.unwrap();
-    } else {
-        sqlx::query!("UPDATE user_quest SET progress = 'denied' WHERE id = $1", qid)
-            .execute(&state.db_connection)
-            .await.unwrap();
+    match verify_tx(&state, qid, body.completed).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("verify: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
+}
 
-    StatusCode::OK
+// Only pending requests can be decided, so verifying twice doesn't give the points twice
+async fn verify_tx(state: &AppState, qid: Uuid, completed: bool) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let mut after = AfterCommit::default();
+    if completed {
+        let res = sqlx::query!("UPDATE user_quest uq SET progress = 'verified', \
+                points_awarded = ROUND(q.points_received * COALESCE((SELECT c.points_multiplier FROM campaigns c WHERE c.id = uq.campaign_id), 1))::integer \
+            FROM quests q WHERE uq.id = $1 AND uq.progress = 'pending' AND q.id = uq.quest_id \
+            RETURNING uq.user_id, uq.quest_id, uq.points_awarded, q.name;", qid)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(r) = res else {
+            return Ok(StatusCode::NOT_FOUND);
+        };
+        award_points(&mut tx, state, &mut after, r.user_id, r.points_awarded).await?;
+        // The weekly quest is completed with complete_challenge, never through an admin
+        quest_completed(&mut tx, state, &mut after, r.user_id, r.quest_id, qid, false).await?;
+        let n = notifications::emit(&mut *tx, r.user_id, data::NotificationKind::QuestVerified, "Предизвикателството е потвърдено",
+            &format!("„{}“: +{} точки", r.name, r.points_awarded), Some(r.quest_id)).await?;
+        after.notify(r.user_id, n);
+        after.commit(tx, state).await?;
+        if let Err(e) = achievements::evaluate(state, r.user_id).await {
+            eprintln!("achievements: {:?}", e);
+        }
+    } else {
+        let res = sqlx::query!("UPDATE user_quest uq SET progress = 'denied' FROM quests q WHERE uq.id = $1 AND uq.progress = 'pending' AND q.id = uq.quest_id \
+            RETURNING uq.user_id, uq.quest_id, q.name;", qid)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(r) = res else {
+            return Ok(StatusCode::NOT_FOUND);
+        };
+        let n = notifications::emit(&mut *tx, r.user_id, data::NotificationKind::QuestDenied, "Предизвикателството не е потвърдено",
+            &format!("„{}“ може да бъде опитано отново", r.name), Some(r.quest_id)).await?;
+        after.notify(r.user_id, n);
+        after.commit(tx, state).await?;
+    }
+    Ok(StatusCode::OK)
 }
 
-// Allows a user to complete a quest and get it auto-verified (no admin required).
-// This creates a user_quest with progress = 'verified' and awards the quest's points to the user.
+// Allows a user to complete a quest and get it auto-verified (no admin required), if the quest allows self-completion.
+// This creates a user_quest with progress = 'verified' and awards the quest's points to the user, all in one transaction.
+// When an Idempotency-Key header is sent, retries with the same key get the first answer back instead of completing again.
 pub async fn complete_challenge(State(state): State<data::AppState>, headers: HeaderMap, Path(qid): Path<Uuid>) -> StatusCode {
     let token = match headers.get("user_id") {
         Some(t) => t,
@@ -240,58 +389,180 @@ pub async fn complete_challenge(State(state): State<data::AppState>, headers: He
         Ok(s) => match s.parse() { Ok(u) => u, Err(_) => return StatusCode::UNAUTHORIZED },
         Err(_) => return StatusCode::UNAUTHORIZED,
     };
+    let idempotency_key = match headers.get("Idempotency-Key").map(|k| k.to_str()) {
+        Some(Ok(k)) if !k.is_empty() && k.len() <= 255 => Some(k.to_string()),
+        Some(_) => return StatusCode::BAD_REQUEST,
+        None => None,
+    };
 
-    let quest = sqlx::query_as!(Quest, "SELECT * FROM quests WHERE id = $1;", qid)
-        .fetch_one(&state.db_connection)
-        .await.unwrap();
+    let res = complete_challenge_tx(&state, uid, qid, idempotency_key.as_deref()).await;
+    match res {
+        Ok(status) => {
+            if status == StatusCode::OK {
+                let _ = update_streak(&state, &headers, data::ActivityKind::Quest, 1).await;
+            }
+            status
+        }
+        Err(e) => {
+            eprintln!("complete: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn complete_challenge_tx(state: &AppState, uid: Uuid, qid: Uuid, idempotency_key: Option<&str>) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+
+    if let Some(key) = idempotency_key {
+        // A concurrent request with the same key waits here until the first one commits
+        let claimed = sqlx::query_scalar!("INSERT INTO idempotency_keys (user_id, key) VALUES ($1, $2) ON CONFLICT DO NOTHING RETURNING key;", uid, key)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if claimed.is_none() {
+            let status = sqlx::query_scalar!("SELECT status_code FROM idempotency_keys WHERE user_id = $1 AND key = $2;", uid, key)
+                .fetch_one(&mut *tx)
+                .await?;
+            return Ok(status.and_then(|s| StatusCode::from_u16(s as u16).ok()).unwrap_or(StatusCode::CONFLICT));
+        }
+    }
+
+    lock_user(&mut tx, uid).await?;
+    let mut after = AfterCommit::default();
+    let status = match get_quest_history(&mut *tx, uid, qid).await {
+        Ok(quest) => {
+            if !quest.quest.self_complete {
+                // Has to go through /challange/send and get verified by an admin
+                StatusCode::FORBIDDEN
+            } else if !matches!(quest_availability(&quest, Utc::now().date_naive()), Availability::Now) {
+                // The repeat policy decides if the quest can be done (again) today
+                StatusCode::CONFLICT
+            } else if quest.quest.required_checkins > 1 {
+                // Multi-step quests are completed through check-ins
+                StatusCode::BAD_REQUEST
+            } else {
+                let points = quest_points(&quest);
+                record_completion(&mut tx, state, &mut after, uid, &quest, points, points).await?;
+                StatusCode::OK
+            }
+        }
+        Err(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+        Err(e) => return Err(e),
+    };
+
+    if let Some(key) = idempotency_key {
+        sqlx::query!("UPDATE idempotency_keys SET status_code = $3 WHERE user_id = $1 AND key = $2;", uid, key, status.as_u16() as i32)
+            .execute(&mut *tx)
+            .await?;
+    }
+    after.commit(tx, state).await?;
+    Ok(status)
+}
+
+// The quest's points including the bonus of the campaign it's part of
+pub(crate) fn quest_points(quest: &data::OfferedQuest) -> i32 {
+    (quest.quest.points_received as f32 * quest.points_multiplier.unwrap_or(1.0)).round() as i32
+}
 
-    if quest.points_received == WEEKLY_POINTS {
+// Stores a verified completion worth `points` and adds `new_points` to the user's total.
+// They differ only when part of the points were already given out during check-ins.
+pub(crate) async fn record_completion(tx: &mut PgConnection, state: &AppState, after: &mut AfterCommit, uid: Uuid, quest: &data::OfferedQuest, points: i32, new_points: i32) -> Result<(), sqlx::Error> {
+    let weekly = is_weekly(state, quest.quest.id).await;
+    if weekly {
         sqlx::query!("UPDATE users SET completed_weekly = NOW() WHERE id = $1", uid)
-            .execute(&state.db_connection)
-            .await.unwrap();
+            .execute(&mut *tx)
+            .await?;
     }
 
     // Insert a verified user_quest row for this user and quest
-    let inserted = sqlx::query("INSERT INTO user_quest (user_id, quest_id, progress, proof_path) VALUES ($1, $2, $3, $4) RETURNING id;")
+    let id = sqlx::query_scalar::<_, Uuid>("INSERT INTO user_quest (user_id, quest_id, progress, proof_path, points_awarded, campaign_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id;")
         .bind(uid)
-        .bind(qid)
+        .bind(quest.quest.id)
         .bind(data::Progress::Verified)
         .bind("")
-        .fetch_one(&state.db_connection)
-        .await;
+        .bind(points)
+        .bind(quest.campaign_id)
+        .fetch_one(&mut *tx)
+        .await?;
 
-    if inserted.is_err() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    }
+    award_points(tx, state, after, uid, new_points).await?;
+    quest_completed(tx, state, after, uid, quest.quest.id, id, weekly).await
+}
 
-    // Fetch quest points and add to user's total
-    let quest_row = sqlx::query!("SELECT points_received FROM quests WHERE id = $1", qid)
-        .fetch_one(&state.db_connection)
-        .await;
+// Everything that follows a verified completion: the activity feed, group feeds and quests, peer challenges
+pub(crate) async fn quest_completed(tx: &mut PgConnection, state: &AppState, after: &mut AfterCommit, uid: Uuid, quest_id: Uuid, user_quest_id: Uuid, weekly: bool) -> Result<(), sqlx::Error> {
+    feed::quest_completed(tx, uid, quest_id, user_quest_id, weekly).await?;
+    groups::quest_completed(tx, state, after, uid, quest_id).await?;
+    peer_challenges::quest_completed(tx, state, after, uid, quest_id).await
+}
 
-    match quest_row {
-        Ok(q) => {
-            let _ = sqlx::query!("UPDATE users SET points = points + $1 WHERE id = $2", q.points_received, uid)
-                .execute(&state.db_connection)
-                .await;
-            let _ = update_streak(&state, &headers).await;
-            StatusCode::OK
+// Changes to the cached leaderboards, live events and notifications that a transaction made. They are collected
+// while it runs and applied once it commits, so a rollback leaves nothing behind on the boards or the users' devices.
+#[derive(Default)]
+pub(crate) struct AfterCommit {
+    points: Vec<(Uuid, i32)>,
+    events: Vec<(Uuid, data::LiveEvent)>,
+    notifications: Vec<(Uuid, data::Notification)>,
+}
+
+impl AfterCommit {
+    // Takes what notifications::emit returned, nothing is sent when the user turned the kind off
+    pub(crate) fn notify(&mut self, uid: Uuid, notification: Option<data::Notification>) {
+        if let Some(n) = notification {
+            self.notifications.push((uid, n));
+        }
+    }
+
+    // Commits the transaction and applies what was collected. Leaderboards built in the meantime aren't cached.
+    pub(crate) async fn commit(self, tx: Transaction<'_, Postgres>, state: &AppState) -> Result<(), sqlx::Error> {
+        let committing = (!self.points.is_empty()).then(|| leaderboard::Committing::start(state));
+        tx.commit().await?;
+        for (uid, points) in self.points {
+            leaderboard::apply_points(state, uid, points).await;
+        }
+        drop(committing);
+        for (uid, event) in self.events {
+            events::publish(state, uid, event);
         }
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        for (uid, n) in self.notifications {
+            notifications::deliver(state, uid, n);
+        }
+        Ok(())
     }
 }
 
+// Every point award goes through here. It's logged in points_history, moves the user on the cached leaderboards
+// and tells their /api/events streams once the transaction commits. Crossing level thresholds records a level-up for each level reached.
+pub(crate) async fn award_points(tx: &mut PgConnection, state: &AppState, after: &mut AfterCommit, uid: Uuid, points: i32) -> Result<(), sqlx::Error> {
+    let total = sqlx::query_scalar!("UPDATE users SET points = points + $1 WHERE id = $2 RETURNING points", points, uid)
+        .fetch_one(&mut *tx)
+        .await?;
+    sqlx::query!("INSERT INTO points_history (user_id, points) VALUES ($1, $2);", uid, points)
+        .execute(&mut *tx)
+        .await?;
+
+    let from = levels::level_for(&state.level_curve, total - points).level;
+    let to = levels::level_for(&state.level_curve, total).level;
+    after.points.push((uid, points));
+    after.events.push((uid, data::LiveEvent::Points { points: total, level: to }));
+    if to > from {
+        sqlx::query!("INSERT INTO level_ups (user_id, level) SELECT $1, generate_series($2::integer, $3::integer);", uid, from + 1, to)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
 async fn get_random_quest(target_pts: Option<i32>, state: &AppState) -> Quest {
-    let mut quests = Vec::<Quest>::new();
-    if let Some(pts) = target_pts {
-        quests = sqlx::query_as!(Quest, "SELECT * FROM quests WHERE points_received = $1;", pts)
+    let quests = if let Some(pts) = target_pts {
+        sqlx::query_as::<_, Quest>("SELECT * FROM quests WHERE points_received = $1;")
+            .bind(pts)
             .fetch_all(&state.db_connection)
-            .await.expect("a");
+            .await.expect("a")
     } else {
-        quests = sqlx::query_as!(Quest, "SELECT * FROM quests;")
+        sqlx::query_as::<_, Quest>("SELECT * FROM quests;")
             .fetch_all(&state.db_connection)
-            .await.expect("a");
-    }
+            .await.expect("a")
+    };
     let mut rng = rand::rng();
     let idx = rng.random_range(0..quests.len());
     let the_chosen_one = quests.get(idx).unwrap();
@@ -299,24 +570,37 @@ async fn get_random_quest(target_pts: Option<i32>, state: &AppState) -> Quest {
     the_chosen_one.clone()
 }
 
-async fn get_weekly(state: &AppState) -> Quest {
-
-    let mut last_week = {
-        state.last_week.lock().await
-    };
-    let mut weekly_challange = {
-        state.weekly_challange.lock().await
-    };
+// Whether the quest is this week's weekly quest. Other quests can be worth as many points.
+async fn is_weekly(state: &AppState, quest_id: Uuid) -> bool {
+    state.weekly_challange.lock().await.as_ref().is_some_and(|q| q.id == quest_id)
+}
 
+// Completions look the weekly up while they hold their user's row lock, so the locks here are never held
+// across a query. When two requests pick a new quest at once, the one stored first wins and only it is announced.
+async fn get_weekly(state: &AppState) -> Quest {
     let now = Utc::now().date_naive().iso_week().week();
-    if last_week.is_none() {
+    {
+        let last_week = state.last_week.lock().await;
+        let weekly_challange = state.weekly_challange.lock().await;
+        if *last_week == Some(now) && let Some(quest) = weekly_challange.as_ref() {
+            return quest.clone();
+        }
+    }
+
+    let quest = get_random_quest(Some(WEEKLY_POINTS), state).await;
+    {
+        let mut last_week = state.last_week.lock().await;
+        let mut weekly_challange = state.weekly_challange.lock().await;
+        if *last_week == Some(now) && let Some(stored) = weekly_challange.as_ref() {
+            return stored.clone();
+        }
         *last_week = Some(now);
+        *weekly_challange = Some(quest.clone());
     }
-    if weekly_challange.is_none() || last_week.unwrap() != now {
-        let quest = get_random_quest(Some(50), &state);
-        *weekly_challange = Some(quest.await);
+    if let Err(e) = notifications::weekly_quest(state, &quest).await {
+        eprintln!("weekly quest notifications: {:?}", e);
     }
-    weekly_challange.as_ref().unwrap().clone()
+    quest
 }
 
 pub async fn get_weekly_quest(headers: HeaderMap, State(state): State<data::AppState>) -> Result<Json<Quest>, StatusCode> {
@@ -336,17 +620,92 @@ pub async fn get_weekly_quest(headers: HeaderMap, State(state): State<data::AppS
     Ok(Json(the_chosen_one))
 }
 
-// Accepts number only
-pub async fn send_form_points(State(state): State<data::AppState>, headers: HeaderMap, Json(pts): Json<i32>) -> StatusCode {
+// Clients from before /api/questionnaire still send the questionnaire here. The number they send used to become
+// the user's points, now it's ignored and the questionnaire is rewarded like on /api/questionnaire.
+pub async fn send_form_points(State(state): State<data::AppState>, headers: HeaderMap, Json(_pts): Json<i32>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = questionnaire_tx(&state, uid, &[], &[]).await;
+    match res {
+        Ok(status) => {
+            // The old questionnaire has questions for all five categories
+            if status == StatusCode::OK {
+                let _ = update_streak(&state, &headers, data::ActivityKind::Questionnaire, 5).await;
+            }
+            status
+        }
+        Err(e) => {
+            eprintln!("form points: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    let token = headers.get("user_id").unwrap();
-    let id: Uuid = token.to_str().unwrap().parse().unwrap();
+// Stores the questionnaire result per category, the wheel leans towards the low ones
+pub async fn send_questionnaire(State(state): State<AppState>, headers: HeaderMap, Json(body): Json<data::QuestionnaireInput>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    if body.scores.is_empty() || body.scores.values().any(|s| !(0..=4).contains(s)) {
+        return StatusCode::BAD_REQUEST;
+    }
 
-    sqlx::query!("UPDATE users SET points = $1 WHERE id = $2;", pts, id)
-        .execute(&state.db_connection)
-        .await.unwrap();
+    let answered = body.scores.len() as i32;
+    let (categories, scores): (Vec<data::ChallengeCategory>, Vec<i32>) = body.scores.into_iter().unzip();
+    let res = questionnaire_tx(&state, uid, &categories, &scores).await;
+    match res {
+        Ok(status) => {
+            if status == StatusCode::OK {
+                let _ = update_streak(&state, &headers, data::ActivityKind::Questionnaire, answered).await;
+            }
+            status
+        }
+        Err(e) => {
+            eprintln!("questionnaire: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
 
-    StatusCode::OK
+// Saves the scores and gives QUESTIONNAIRE_POINTS for the first questionnaire of the day
+async fn questionnaire_tx(state: &AppState, uid: Uuid, categories: &[data::ChallengeCategory], scores: &[i32]) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let mut after = AfterCommit::default();
+    let res = sqlx::query("INSERT INTO questionnaire_scores (user_id, category, score) SELECT $1, * FROM UNNEST($2::challenge_category[], $3::int[]);")
+        .bind(uid)
+        .bind(categories)
+        .bind(scores)
+        .execute(&mut *tx)
+        .await;
+    match res {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => return Ok(StatusCode::UNAUTHORIZED),
+        Err(e) => return Err(e),
+    }
+
+    let first = sqlx::query!("INSERT INTO questionnaire_rewards (user_id, day) VALUES ($1, $2) ON CONFLICT DO NOTHING;", uid, Utc::now().date_naive())
+        .execute(&mut *tx)
+        .await;
+    match first {
+        Ok(r) if r.rows_affected() > 0 => award_points(&mut tx, state, &mut after, uid, QUESTIONNAIRE_POINTS).await?,
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => return Ok(StatusCode::UNAUTHORIZED),
+        Err(e) => return Err(e),
+    }
+    after.commit(tx, state).await?;
+    Ok(StatusCode::OK)
 }
 
 pub async fn admin_check(headers: HeaderMap, State(state): State<AppState>, req: Request, next: Next, ) -> Result<Response, StatusCode> {
@@ -363,181 +722,9 @@ pub async fn admin_check(headers: HeaderMap, State(state): State<AppState>, req:
     }
 }
 
-pub async fn leaderboard(State(state): State<AppState>) -> Result<Json<Vec<(String, i32, i64)>>, StatusCode> {
-
-    // Change the limit or some shit.
-    let users = sqlx::query!("SELECT name,points, RANK() OVER (ORDER BY points DESC) AS rank FROM users LIMIT 5;")
-        .fetch_all(&state.db_connection)
-        .await;
-    match users {
-        Ok(users) => {
-            let users: Vec<(String, i32, i64)> = users.iter().into_iter()
-                .map(|u| (u.name.clone(), u.points, u.rank.unwrap_or(0))).collect();
-            Ok(Json(users))
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR)
-    }
-}
-
-
-// Wheel
-#[derive(Clone, Serialize)]
-pub struct Challenge {
-    title: &'static str,
-    description: &'static str,
-}
-
-static CHALLENGES: [Challenge; 33] = [
-        Challenge {
-            title: "24-hour Digital Sunset",
-            description: "No screens from sunset to sunrise; write a short reflection.",
-        },
-        Challenge {
-            title: "Trash Audit + Swap",
-            description: "Categorize household waste for 48h and replace one single-use item.",
-        },
-        Challenge {
-            title: "Walk & Listen",
-            description: "90-min walk without phone navigation; note 10 plants/animals.",
-        },
-        Challenge {
-            title: "No-Complaint Day",
-            description: "Reframe complaints into constructive action for one day.",
-        },
-        Challenge {
-            title: "Repair Project",
-            description: "Repair a broken item or responsibly recycle it.",
-        },
-        Challenge {
-            title: "Give 1 Hour to a Green Group",
-            description: "Volunteer an hour with a local environmental group.",
-        },
-        Challenge {
-            title: "Social Media Fast",
-            description: "Take 72h off one social platform and write by hand to a friend.",
-        },
-        Challenge {
-            title: "Teach a Skill",
-            description: "Teach someone a practical green skill (composting, repair).",
-        },
-        Challenge {
-            title: "One-Ingredient Cooking",
-            description: "Cook a meal with local seasonal ingredients only.",
-        },
-        Challenge {
-            title: "Deep Conversation",
-            description: "A 30-min distraction-free call with someone you care about.",
-        },
-        Challenge {
-            title: "24-Hour Plastic-Free",
-            description: "Avoid single-use plastics for one full day.",
-        },
-        Challenge {
-            title: "Mindful Mornings",
-            description: "10 minutes of mindfulness each morning for 7 days.",
-        },
-        Challenge {
-            title: "Sleep Hygiene Reset",
-            description: "Set a sleep routine for 7 nights; no screens 1h before bed.",
-        },
-        Challenge {
-            title: "Community Swap",
-            description: "Organize or join a swap for clothes/tools/books.",
-        },
-        Challenge {
-            title: "Zero-Waste Lunch x3",
-            description: "Pack waste-free lunches for three days.",
-        },
-        Challenge {
-            title: "Conversation with a Stranger",
-            description: "Have a respectful 10–15 minute chat with someone new.",
-        },
-        Challenge {
-            title: "30-Day Gratitude Thread",
-            description: "Write one gratitude note each day for 30 days.",
-        },
-        Challenge {
-            title: "Plant a Small Habitat",
-            description: "Create a pollinator planter and observe it for a week.",
-        },
-        Challenge {
-            title: "Energy Audit",
-            description: "Estimate big energy uses and implement 3 reductions.",
-        },
-        Challenge {
-            title: "Leftover Reinvention",
-            description: "Turn leftovers into a new creative dish.",
-        },
-        Challenge {
-            title: "Difficult Conversation",
-            description: "Plan and have a calm talk you've been avoiding.",
-        },
-        Challenge {
-            title: "Public Transport Pledge",
-            description: "Use transit/bike/walk for two consecutive days.",
-        },
-        Challenge {
-            title: "Local Economy Day",
-            description: "Buy only locally-sourced goods for one day.",
-        },
-        Challenge {
-            title: "Nature Micro-Project",
-            description: "Plant native seeds or make a bee hotel.",
-        },
-        Challenge {
-            title: "One Week Blocked App",
-            description: "Block an addictive app for one week.",
-        },
-        Challenge {
-            title: "Short Story From the Park",
-            description: "90 min outside, then write a 500-word flash story.",
-        },
-        Challenge {
-            title: "Upcycle Challenge",
-            description: "Turn an old item into something new to gift.",
-        },
-        Challenge {
-            title: "Mindful Listening Night",
-            description: "Host a 1-hour listening session with a friend.",
-        },
-        Challenge {
-            title: "Repair Café",
-            description: "Attend or organize a repair event.",
-        },
-        Challenge {
-            title: "One-Day Minimalist",
-            description: "Live with only 20 items for a day.",
-        },
-        Challenge {
-            title: "Screen-Free Creative Sprint",
-            description: "2 hours creating with zero screens.",
-        },
-        Challenge {
-            title: "Eco-Advocacy Letter",
-            description: "Send a short evidence-based letter to a local official.",
-        },
-        Challenge {
-            title: "Friendship Audit",
-            description: "Pick one friendship to invest in this month.",
-        },
-];
-
-
-pub async fn wheel_spin() -> (StatusCode, Json<i32>) {
-    let n = CHALLENGES.len();
-    if n == 0 {
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(0i32))
-    }
-    let mut rng = rand::rng();
-    let idx = rng.random_range(0..n) as i32;
-    (StatusCode::OK, Json(idx))
-}
-
-pub async fn get_wheel_challanges()  -> (StatusCode, Json<Vec<Challenge>>) {
-    (StatusCode::OK, Json(CHALLENGES.to_vec()))
-}
-
-async fn update_streak(state: &AppState, headers: &HeaderMap) -> Result<i32, StatusCode> {
+// Counts an activity towards the caller's streak for today if the activity rules allow it, and returns the streak then.
+// `amount` is what the rule's minimum is checked against, see activity_rules in init.sql.
+pub(crate) async fn update_streak(state: &AppState, headers: &HeaderMap, kind: data::ActivityKind, amount: i32) -> Result<Option<i32>, StatusCode> {
     let user_id = match headers.get("user_id") {
         Some(u) => u,
         None => return Err(StatusCode::UNAUTHORIZED)
@@ -546,72 +733,29 @@ async fn update_streak(state: &AppState, headers: &HeaderMap) -> Result<i32, Sta
         Ok(u) => u,
         Err(_) => return Err(StatusCode::UNAUTHORIZED)
     };
-    let user = query_as!(data::User, "SELECT * FROM users WHERE id = $1;", user_id)
-        .fetch_one(&state.db_connection)
-        .await;
-    match user {
-        Ok(user) => {
-            let today = Utc::now().date_naive();
-            let yesterday = today.checked_sub_days(Days::new(1)).unwrap();
-            let mut streak = 0;
-            
-            if user.last_active == today {
-                streak = user.current_streak;
-            } else if user.last_active != yesterday {
-                sqlx::query!("UPDATE users SET current_streak = 0 WHERE id = $1;", user_id)
-                    .execute(&state.db_connection).await.unwrap();
-            } else {
-                let updated = sqlx::query_as!(data::User, "UPDATE users SET current_streak = current_streak + 1, longest_streak = GREATEST(current_streak + 1, longest_streak), last_active = $2 WHERE id = $1 RETURNING *;",
-                            user_id, today)
-                    .fetch_one(&state.db_connection).await.unwrap();
-                streak = updated.current_streak;
+
+    let streak = match streaks::qualifies(state, kind, amount).await {
+        Ok(true) => match streaks::record_activity(state, user_id, kind).await {
+            Ok(s) => Some(s),
+            Err(e) => {
+                eprintln!("streak: {:?}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
             }
-            
-            Ok(streak)
         },
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR)
-    }
-}
-
-pub async fn get_streak(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<serde_json::Value>, StatusCode> {
-
-    let user_id = match headers.get("user_id") {
-        Some(u) => u,
-        None => return Err(StatusCode::UNAUTHORIZED)
-    };
-    let user_id = match Uuid::from_str(user_id.to_str().unwrap()) {
-        Ok(u) => u,
-        Err(_) => return Err(StatusCode::UNAUTHORIZED)
+        Ok(false) => None,
+        Err(e) => {
+            eprintln!("streak: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
     };
 
-    let user = query_as!(data::User, "SELECT * FROM users WHERE id = $1;", user_id)
-        .fetch_one(&state.db_connection)
-        .await;
-    match user {
-        Ok(user) => {
-            let today = Utc::now().date_naive();
-            let yesterday = today.checked_sub_days(Days::new(1)).unwrap();
-            let mut streak = 0;
-            let mut longest_streak = 0;
-            if user.last_active == today {
-                streak = user.current_streak;
-                longest_streak = user.longest_streak;
-            }
-            else if user.last_active != yesterday {
-                sqlx::query!("UPDATE users SET current_streak = 0 WHERE id = $1;", user_id)
-                    .execute(&state.db_connection).await.unwrap();
-            }
-            Ok(Json(json!({
-                "current_streak": streak,
-                "longest_streak": longest_streak,
-                "last_completed_date": today
-            })))
-        },
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR)
+    // Every activity ends up here, so this is where achievements get checked
+    if let Err(e) = achievements::evaluate(state, user_id).await {
+        eprintln!("achievements: {:?}", e);
     }
+    Ok(streak)
 }
 
-
 pub async fn pchallange_create(headers: HeaderMap, State(state): State<AppState>, Json(quest): Json<PersonalChallangeInput>) -> StatusCode {
 
     let user_id = match headers.get("user_id") {
@@ -622,13 +766,22 @@ pub async fn pchallange_create(headers: HeaderMap, State(state): State<AppState>
         Ok(u) => u,
         Err(_) => return StatusCode::INTERNAL_SERVER_ERROR
     };
+    if !GOAL_PRIORITY.contains(&quest.priority) {
+        return StatusCode::BAD_REQUEST;
+    }
 
-    let r = sqlx::query!("INSERT INTO personal_challanges (category, description, name, priority, user_id) VALUES ($1, $2, $3, $4, $5)",
-        quest.category, quest.description, quest.name, quest.priority, user_id)
+    let r = sqlx::query("INSERT INTO personal_challanges (category, description, name, priority, user_id, due_date) VALUES ($1, $2, $3, $4, $5, $6)")
+        .bind(quest.category)
+        .bind(&quest.description)
+        .bind(&quest.name)
+        .bind(quest.priority)
+        .bind(user_id)
+        .bind(quest.due_date)
         .execute(&state.db_connection)
         .await;
 
     if r.is_ok() {
+        let _ = update_streak(&state, &headers, data::ActivityKind::Goal, quest.description.chars().count() as i32).await;
         StatusCode::OK
     } else {
         eprintln!("{:?}", r);
@@ -636,7 +789,9 @@ pub async fn pchallange_create(headers: HeaderMap, State(state): State<AppState>
     }
 }
 
-pub async fn pchallange_get(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<PersonalChallange>>, StatusCode> {
+// The caller's goals, newest first unless sorted by priority or due date.
+// Goals without a due date come last when sorting by it.
+pub async fn pchallange_get(headers: HeaderMap, State(state): State<AppState>, Query(query): Query<GoalQuery>) -> Result<Json<Vec<PersonalChallange>>, StatusCode> {
     let user_id = match headers.get("user_id") {
         Some(u) => u,
         None => return Err(StatusCode::UNAUTHORIZED)
@@ -646,15 +801,73 @@ pub async fn pchallange_get(headers: HeaderMap, State(state): State<AppState>) -
         Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR)
     };
 
-    let r = sqlx::query_as!(PersonalChallange, "SELECT * FROM personal_challanges WHERE user_id = $1;", user_id)
+    let order = match query.sort {
+        GoalSort::Created => "created_at DESC, id",
+        GoalSort::Priority => "priority, created_at DESC, id",
+        GoalSort::DueDate => "due_date NULLS LAST, priority, id",
+    };
+    let r = sqlx::query_as::<_, PersonalChallange>(&format!("SELECT * FROM personal_challanges \
+        WHERE user_id = $1 AND ($2::goal_status IS NULL OR status = $2) AND ($3::goal_category IS NULL OR category = $3) \
+        ORDER BY {};", order))
+        .bind(user_id)
+        .bind(query.status)
+        .bind(query.category)
         .fetch_all(&state.db_connection)
         .await;
 
     match r {
         Ok(data) => Ok(Json(data)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR)
+        Err(e) => {
+            eprintln!("goals: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+
+}
+
+// Changes the sent fields of one of the caller's goals and returns it.
+// Completing a goal keeps the time it was first completed, any other status clears it.
+pub async fn pchallange_update(headers: HeaderMap, State(state): State<AppState>, Path(qid): Path<Uuid>, Json(body): Json<PersonalChallangeUpdate>) -> Result<Json<PersonalChallange>, StatusCode> {
+    let user_id = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let user_id: Uuid = match user_id.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    if body.priority.is_some_and(|p| !GOAL_PRIORITY.contains(&p)) {
+        return Err(StatusCode::BAD_REQUEST);
     }
 
+    let r = sqlx::query_as::<_, PersonalChallange>("UPDATE personal_challanges SET name = COALESCE($3, name), \
+            description = COALESCE($4, description), category = COALESCE($5, category), priority = COALESCE($6, priority), \
+            due_date = CASE WHEN $8 THEN NULL ELSE COALESCE($7, due_date) END, \
+            status = COALESCE($9, status), \
+            completed_at = CASE WHEN COALESCE($9, status) = 'completed' THEN COALESCE(completed_at, NOW()) END \
+        WHERE id = $1 AND user_id = $2 RETURNING *;")
+        .bind(qid)
+        .bind(user_id)
+        .bind(&body.name)
+        .bind(&body.description)
+        .bind(body.category)
+        .bind(body.priority)
+        .bind(body.due_date)
+        .bind(body.clear_due_date)
+        .bind(body.status)
+        .fetch_optional(&state.db_connection)
+        .await;
+
+    match r {
+        Ok(Some(goal)) => Ok(Json(goal)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        // Names and descriptions that don't fit
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("22001") => Err(StatusCode::BAD_REQUEST),
+        Err(e) => {
+            eprintln!("update goal: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
 pub async fn pchallange_delete(headers: HeaderMap, State(state): State<AppState>, Path(qid): Path<Uuid>) -> StatusCode {
@@ -696,6 +909,7 @@ pub async fn diary_create(headers: HeaderMap, State(state): State<AppState>, Jso
         .execute(&state.db_connection).await;
 
     if r.is_ok() {
+        let _ = update_streak(&state, &headers, data::ActivityKind::Diary, body.content.chars().count() as i32).await;
         StatusCode::OK
     } else {
         StatusCode::INTERNAL_SERVER_ERROR
@@ -746,7 +960,8 @@ pub async fn diary_delete(headers: HeaderMap, State(state): State<AppState>, Pat
 
 pub async fn admin_users(_headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<User>>, StatusCode> {
     
-    let res = sqlx::query_as::<_, User>("SELECT id, name, mail, is_admin, points, longest_streak, current_streak, last_active, completed_weekly, banned FROM users")
+    let res = sqlx::query_as::<_, User>("SELECT id, name, mail, is_admin, points, longest_streak, current_streak, last_active, completed_weekly, banned, streak_freezes, \
+        show_on_leaderboard, display_name, avatar, alias, anonymous, friends_only FROM users")
         .fetch_all(&state.db_connection).await;
 
     match res {
@@ -768,11 +983,17 @@ pub struct AdminChallenge {
     description: String,
     xp: i32,
     difficulty: String,
-    category: String
+    category: String,
+    repeat_policy: RepeatPolicy,
+    repeat_interval_days: Option<i32>,
+    max_completions: Option<i32>,
+    required_checkins: i32,
+    prorate_points: bool,
+    self_complete: bool,
 }
 
 pub async fn admin_challanges(_headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<AdminChallenge>>, StatusCode> {
-    let challanges = sqlx::query_as!(Quest, "SELECT * FROM quests;")
+    let challanges = sqlx::query_as::<_, Quest>("SELECT * FROM quests;")
         .fetch_all(&state.db_connection).await;
     match challanges {
         Ok(c) => {
@@ -784,7 +1005,13 @@ pub async fn admin_challanges(_headers: HeaderMap, State(state): State<AppState>
                     description: challange.description,
                     xp: challange.points_received,
                     difficulty: if challange.points_received <= 10 {"easy".into()} else if challange.points_received <= 15 {"medium".into()} else {"hard".into()},
-                    category: if challange.points_received == 50 {"weekly".into()} else {"normal".into()}
+                    category: if challange.points_received == 50 {"weekly".into()} else {"normal".into()},
+                    repeat_policy: challange.repeat_policy,
+                    repeat_interval_days: challange.repeat_interval_days,
+                    max_completions: challange.max_completions,
+                    required_checkins: challange.required_checkins,
+                    prorate_points: challange.prorate_points,
+                    self_complete: challange.self_complete,
                 };
                 ac.push(tmp_ac);
             }
@@ -822,27 +1049,60 @@ pub async fn admin_completions(_headers: HeaderMap, State(state): State<AppState
 
 
 pub async fn admin_ban_user(Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
-    let res = sqlx::query!("UPDATE users SET banned = NOT banned WHERE id = $1;", id)
-        .execute(&state.db_connection).await;
+    let res = ban_tx(&state, id).await;
     match res {
-        Ok(_) => StatusCode::OK,
+        Ok(status) => {
+            leaderboard::invalidate(&state).await;
+            status
+        }
         Err(e) => {
             eprintln!("ban: {:?}", e);
             StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 }
+// Toggles the ban and tells the user about it
+async fn ban_tx(state: &AppState, id: Uuid) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let mut after = AfterCommit::default();
+    let banned = sqlx::query_scalar!("UPDATE users SET banned = NOT banned WHERE id = $1 RETURNING banned;", id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let n = match banned {
+        Some(true) => notifications::emit(&mut *tx, id, data::NotificationKind::Banned, "Профилът ти е блокиран",
+            "Не се показваш в класациите и емисиите на другите", None).await?,
+        Some(false) => notifications::emit(&mut *tx, id, data::NotificationKind::Unbanned, "Профилът ти е отблокиран", "", None).await?,
+        None => return Ok(StatusCode::NOT_FOUND),
+    };
+    after.notify(id, n);
+    after.commit(tx, state).await?;
+    Ok(StatusCode::OK)
+}
+
 pub async fn admin_delete_user(Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
-    let res = sqlx::query!("DELETE FROM users WHERE id = $1;", id)
-        .execute(&state.db_connection).await;
+    let res = delete_user_tx(&state, id).await;
     match res {
-        Ok(_) => StatusCode::OK,
+        Ok(_) => {
+            leaderboard::invalidate(&state).await;
+            StatusCode::OK
+        }
         Err(e) => {
             eprintln!("del: {:?}", e);
             StatusCode::INTERNAL_SERVER_ERROR
         }
     }
 }
+
+async fn delete_user_tx(state: &AppState, id: Uuid) -> Result<(), sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    // Their groups go on without them
+    groups::hand_over_groups(&mut tx, id, None).await?;
+    sqlx::query!("DELETE FROM users WHERE id = $1;", id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
 pub async fn admin_edit_user(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<Value>) -> StatusCode {
     // Set only the uhh tvato admin deto e
     
@@ -854,7 +1114,10 @@ pub async fn admin_edit_user(headers: HeaderMap, Path(id): Path<Uuid>, State(sta
     let res = sqlx::query!("UPDATE users SET is_admin = $2 WHERE id = $1;", id, body["is_admin"].as_bool().unwrap())
         .execute(&state.db_connection).await;
     match res {
-        Ok(_) => StatusCode::OK,
+        Ok(_) => {
+            leaderboard::invalidate(&state).await;
+            StatusCode::OK
+        }
         Err(e) => {
             eprintln!("ban: {:?}", e);
             StatusCode::INTERNAL_SERVER_ERROR
@@ -866,9 +1129,48 @@ pub async fn admin_add_challange(State(state): State<AppState>, Json(body): Json
     let title = body["title"].as_str();
     let description = body["description"].as_str();
     let xp = (body["xp"].as_i64().unwrap()) as i32;
+    let repeat_policy: RepeatPolicy = serde_json::from_value(body["repeat_policy"].clone()).unwrap_or_default();
+    let repeat_interval_days = body["repeat_interval_days"].as_i64().map(|d| d as i32);
+    let max_completions = body["max_completions"].as_i64().map(|m| m as i32);
+    let steps: Option<Vec<data::QuestStep>> = serde_json::from_value(body["steps"].clone()).ok();
+    // A quest with steps needs one check-in per step
+    let required_checkins = match &steps {
+        Some(steps) if !steps.is_empty() => steps.len() as i32,
+        _ => body["required_checkins"].as_i64().unwrap_or(1) as i32,
+    };
+    let prorate_points = body["prorate_points"].as_bool().unwrap_or(false);
+    // Quests that aren't self-completed wait for an admin to verify them
+    let self_complete = body["self_complete"].as_bool().unwrap_or(true);
 
-    let res = sqlx::query!("INSERT INTO quests (name, description, points_received, required_points) VALUES ($1, $2, $3, $3);", title, description, xp)
-        .execute(&state.db_connection).await;
+    let mut tx = match state.db_connection.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("add challange: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let res = sqlx::query_scalar::<_, Uuid>("INSERT INTO quests (name, description, points_received, required_points, repeat_policy, repeat_interval_days, max_completions, required_checkins, prorate_points, self_complete) \
+        VALUES ($1, $2, $3, $3, $4, $5, $6, $7, $8, $9) RETURNING id;")
+        .bind(title)
+        .bind(description)
+        .bind(xp)
+        .bind(repeat_policy)
+        .bind(repeat_interval_days)
+        .bind(max_completions)
+        .bind(required_checkins)
+        .bind(prorate_points)
+        .bind(self_complete)
+        .fetch_one(&mut *tx).await;
+    // The quest and its steps are saved together, check-in shares depend on the steps being complete
+    let res = match (res, steps) {
+        (Ok(id), Some(steps)) => save_quest_steps(&mut tx, id, &steps).await,
+        (Ok(_), None) => Ok(()),
+        (Err(e), _) => Err(e),
+    };
+    let res = match res {
+        Ok(()) => tx.commit().await,
+        Err(e) => Err(e),
+    };
     match res {
         Ok(_) => StatusCode::OK,
         Err(e) => {
@@ -882,9 +1184,55 @@ pub async fn admin_edit_challange(Path(id): Path<Uuid>, State(state): State<AppS
     let title = body["title"].as_str();
     let description = body["description"].as_str();
     let xp = (body["xp"].as_i64().unwrap()) as i32;
+    // The repeat settings are only changed when they are sent, so older clients don't reset them
+    let repeat_policy: Option<RepeatPolicy> = serde_json::from_value(body["repeat_policy"].clone()).ok();
+    let repeat_interval_days = body["repeat_interval_days"].as_i64().map(|d| d as i32);
+    let max_completions = body["max_completions"].as_i64().map(|m| m as i32);
+    let steps: Option<Vec<data::QuestStep>> = serde_json::from_value(body["steps"].clone()).ok();
+    let required_checkins = match &steps {
+        Some(steps) if !steps.is_empty() => Some(steps.len() as i32),
+        _ => body["required_checkins"].as_i64().map(|c| c as i32),
+    };
+    let prorate_points = body["prorate_points"].as_bool();
+    let self_complete = body["self_complete"].as_bool();
 
-    let res = sqlx::query!("UPDATE quests SET name = $1, description = $2, points_received = $3, required_points = $3 WHERE id = $4;", title, description, xp, id)
-        .execute(&state.db_connection).await;
+    let mut tx = match state.db_connection.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("edit challange: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let res = sqlx::query("UPDATE quests SET name = $1, description = $2, points_received = $3, required_points = $3, \
+        repeat_policy = COALESCE($4, repeat_policy), \
+        repeat_interval_days = CASE WHEN $5 THEN $6 ELSE repeat_interval_days END, \
+        max_completions = CASE WHEN $7 THEN $8 ELSE max_completions END, \
+        required_checkins = COALESCE($10, required_checkins), \
+        prorate_points = COALESCE($11, prorate_points), \
+        self_complete = COALESCE($12, self_complete) \
+        WHERE id = $9;")
+        .bind(title)
+        .bind(description)
+        .bind(xp)
+        .bind(repeat_policy)
+        .bind(body.get("repeat_interval_days").is_some())
+        .bind(repeat_interval_days)
+        .bind(body.get("max_completions").is_some())
+        .bind(max_completions)
+        .bind(id)
+        .bind(required_checkins)
+        .bind(prorate_points)
+        .bind(self_complete)
+        .execute(&mut *tx).await;
+    let res = match (res, steps) {
+        (Ok(_), Some(steps)) => save_quest_steps(&mut tx, id, &steps).await,
+        (Ok(_), None) => Ok(()),
+        (Err(e), _) => Err(e),
+    };
+    let res = match res {
+        Ok(()) => tx.commit().await,
+        Err(e) => Err(e),
+    };
     match res {
         Ok(_) => StatusCode::OK,
         Err(e) => {
@@ -894,6 +1242,20 @@ pub async fn admin_edit_challange(Path(id): Path<Uuid>, State(state): State<AppS
     }
 }
 
+// Replaces the steps of a quest, they are numbered in the order they were sent
+async fn save_quest_steps(tx: &mut PgConnection, qid: Uuid, steps: &[data::QuestStep]) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM quest_steps WHERE quest_id = $1;", qid)
+        .execute(&mut *tx)
+        .await?;
+    for (i, step) in steps.iter().enumerate() {
+        sqlx::query!("INSERT INTO quest_steps (quest_id, position, name, description) VALUES ($1, $2, $3, $4);",
+            qid, i as i32 + 1, step.name, step.description)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
 pub async fn admin_delete_challange(Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
     let res = sqlx::query!("DELETE FROM quests WHERE id = $1;", id)
         .execute(&state.db_connection).await;
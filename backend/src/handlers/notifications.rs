@@ -0,0 +1,228 @@
+use axum::{Json, extract::{Path, Query, State}, http::{HeaderMap, StatusCode}};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::data::{AppState, LiveEvent, Notification, NotificationKind, NotificationPreference, NotificationQuery, Notifications, Quest, UnreadCount};
+use super::{events, push};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 50;
+
+// Every notification goes through here, users who turned the kind off don't get it (None comes back).
+// The stored notification still has to be handed to `deliver`, after the caller's transaction commits.
+pub(crate) async fn emit(db: impl PgExecutor<'_>, uid: Uuid, kind: NotificationKind, title: &str, body: &str, quest_id: Option<Uuid>) -> Result<Option<Notification>, sqlx::Error> {
+    sqlx::query_as::<_, Notification>("INSERT INTO notifications (user_id, kind, title, body, quest_id) SELECT $1, $2, $3, $4, $5 \
+        WHERE NOT EXISTS (SELECT 1 FROM notification_preferences p WHERE p.user_id = $1 AND p.kind = $2 AND NOT p.enabled) \
+        RETURNING id, kind, title, body, quest_id, created_at, read_at;")
+        .bind(uid)
+        .bind(kind)
+        .bind(title)
+        .bind(body)
+        .bind(quest_id)
+        .fetch_optional(db)
+        .await
+}
+
+// Pushes a stored notification to the user's devices and open /api/events streams
+pub(crate) fn deliver(state: &AppState, uid: Uuid, notification: Notification) {
+    push::push_notification(state, uid, &notification);
+    events::publish(state, uid, LiveEvent::Notification(notification));
+}
+
+#[derive(sqlx::FromRow)]
+struct Sent {
+    user_id: Uuid,
+    #[sqlx(flatten)]
+    notification: Notification,
+}
+
+// Tells everyone who isn't banned about a new weekly quest, once per week even if the server restarts and picks another one
+pub(crate) async fn weekly_quest(state: &AppState, quest: &Quest) -> Result<(), sqlx::Error> {
+    let sent = sqlx::query_as::<_, Sent>("INSERT INTO notifications (user_id, kind, title, body, quest_id) \
+        SELECT u.id, 'weekly_quest', 'Ново седмично предизвикателство', $1, $2 FROM users u \
+        WHERE NOT u.banned \
+            AND NOT EXISTS (SELECT 1 FROM notification_preferences p WHERE p.user_id = u.id AND p.kind = 'weekly_quest' AND NOT p.enabled) \
+            AND NOT EXISTS (SELECT 1 FROM notifications n WHERE n.user_id = u.id AND n.kind = 'weekly_quest' AND n.created_at >= date_trunc('week', NOW())) \
+        RETURNING user_id, id, kind, title, body, quest_id, created_at, read_at;")
+        .bind(&quest.name)
+        .bind(quest.id)
+        .fetch_all(&state.db_connection)
+        .await?;
+    for s in sent {
+        deliver(state, s.user_id, s.notification);
+    }
+    Ok(())
+}
+
+// The caller's notifications, newest first
+pub async fn notifications(headers: HeaderMap, State(state): State<AppState>, Query(query): Query<NotificationQuery>) -> Result<Json<Notifications>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let items = sqlx::query_as::<_, Notification>("SELECT id, kind, title, body, quest_id, created_at, read_at FROM notifications n \
+        WHERE user_id = $1 AND (NOT $2 OR read_at IS NULL) \
+            AND ($3::uuid IS NULL OR (n.created_at, n.id) < (SELECT b.created_at, b.id FROM notifications b WHERE b.id = $3)) \
+        ORDER BY created_at DESC, id DESC LIMIT $4;")
+        .bind(uid)
+        .bind(query.unread)
+        .bind(query.before)
+        .bind(limit)
+        .fetch_all(&state.db_connection)
+        .await;
+    let unread = unread(&state, uid).await;
+    match (items, unread) {
+        (Ok(items), Ok(unread)) => {
+            let next = if items.len() as i64 == limit { items.last().map(|i| i.id) } else { None };
+            Ok(Json(Notifications { items, unread, next }))
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("notifications: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn unread(state: &AppState, uid: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM notifications WHERE user_id = $1 AND read_at IS NULL;"#, uid)
+        .fetch_one(&state.db_connection)
+        .await
+}
+
+pub async fn unread_count(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<UnreadCount>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match unread(&state, uid).await {
+        Ok(unread) => Ok(Json(UnreadCount { unread })),
+        Err(e) => {
+            eprintln!("unread notifications: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Marking an already read notification again keeps the time it was first read
+pub async fn mark_read(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("UPDATE notifications SET read_at = COALESCE(read_at, NOW()) WHERE id = $1 AND user_id = $2;", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("mark notification read: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn mark_all_read(headers: HeaderMap, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("UPDATE notifications SET read_at = NOW() WHERE user_id = $1 AND read_at IS NULL;", uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("mark notifications read: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Every kind with whether the caller gets it, all of them are on until turned off
+pub async fn get_preferences(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<NotificationPreference>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match preferences(&state, uid).await {
+        Ok(p) => Ok(Json(p)),
+        Err(e) => {
+            eprintln!("notification preferences: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn preferences(state: &AppState, uid: Uuid) -> Result<Vec<NotificationPreference>, sqlx::Error> {
+    sqlx::query_as::<_, NotificationPreference>("SELECT k AS kind, COALESCE(p.enabled, true) AS enabled \
+        FROM unnest(enum_range(NULL::notification_kind)) k LEFT JOIN notification_preferences p ON p.user_id = $1 AND p.kind = k \
+        ORDER BY k;")
+        .bind(uid)
+        .fetch_all(&state.db_connection)
+        .await
+}
+
+// Only the sent kinds change, returns all of them like get_preferences
+pub async fn update_preferences(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<Vec<NotificationPreference>>) -> Result<Json<Vec<NotificationPreference>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let (kinds, enabled): (Vec<NotificationKind>, Vec<bool>) = body.into_iter().map(|p| (p.kind, p.enabled)).unzip();
+    let res = sqlx::query("INSERT INTO notification_preferences (user_id, kind, enabled) SELECT $1, * FROM UNNEST($2::notification_kind[], $3::bool[]) \
+        ON CONFLICT (user_id, kind) DO UPDATE SET enabled = EXCLUDED.enabled;")
+        .bind(uid)
+        .bind(kinds)
+        .bind(enabled)
+        .execute(&state.db_connection)
+        .await;
+    let res = match res {
+        Ok(_) => preferences(&state, uid).await,
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(p) => Ok(Json(p)),
+        // The same kind twice
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("21000") => Err(StatusCode::BAD_REQUEST),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("notification preferences: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use axum::{Json, extract::{Path, Query, State}, http::{HeaderMap, StatusCode}};
+use chrono::{Days, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::data::{AppState, PeerChallenge, PeerChallengeInput, PeerChallengeQuery};
+use super::{AfterCommit, award_points};
+
+// Bonus for each of the two once both completed the challenge
+const PEER_CHALLENGE_BONUS: i32 = 20;
+const MAX_DEADLINE_DAYS: u64 = 30;
+const MESSAGE_LENGTH: usize = 255;
+const CHALLENGE_HISTORY_SIZE: i64 = 50;
+
+// Challenges a friend to a quest or a wheel challenge, returns the challenge's id.
+// There can only be one open challenge to the same friend for the same thing (409).
+pub async fn create_peer_challenge(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<PeerChallengeInput>) -> Result<Json<Uuid>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let message = body.message.as_deref().map(str::trim).filter(|m| !m.is_empty());
+    let now = Utc::now();
+    if body.quest_id.is_some() == body.wheel_challenge_id.is_some()
+        || message.is_some_and(|m| m.chars().count() > MESSAGE_LENGTH)
+        || body.deadline.is_some_and(|d| d <= now || d > now + Days::new(MAX_DEADLINE_DAYS)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match create_tx(&state, uid, &body, message).await {
+        Ok(res) => res.map(Json),
+        Err(e) => {
+            eprintln!("peer challenge: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn create_tx(state: &AppState, uid: Uuid, body: &PeerChallengeInput, message: Option<&str>) -> Result<Result<Uuid, StatusCode>, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let friend = sqlx::query_scalar!(r#"SELECT CASE WHEN requester_id = $2 THEN addressee_id ELSE requester_id END AS "friend!" FROM friendships
+        WHERE id = $1 AND status = 'accepted' AND (requester_id = $2 OR addressee_id = $2) FOR UPDATE;"#, body.friend, uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(friend) = friend else { return Ok(Err(StatusCode::NOT_FOUND)) };
+
+    let target = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM quests WHERE id = $1)
+        OR EXISTS(SELECT 1 FROM wheel_challenges WHERE id = $2 AND enabled) AS "exists!";"#, body.quest_id, body.wheel_challenge_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if !target {
+        return Ok(Err(StatusCode::NOT_FOUND));
+    }
+
+    let open = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM peer_challenges WHERE challenger_id = $1 AND challenged_id = $2
+        AND quest_id IS NOT DISTINCT FROM $3 AND wheel_challenge_id IS NOT DISTINCT FROM $4
+        AND status IN ('pending', 'accepted') AND (deadline IS NULL OR deadline > NOW())) AS "open!";"#,
+        uid, friend, body.quest_id, body.wheel_challenge_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if open {
+        return Ok(Err(StatusCode::CONFLICT));
+    }
+
+    let id = sqlx::query_scalar!("INSERT INTO peer_challenges (challenger_id, challenged_id, quest_id, wheel_challenge_id, message, deadline, bonus_points)
+        VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id;",
+        uid, friend, body.quest_id, body.wheel_challenge_id, message, body.deadline, PEER_CHALLENGE_BONUS)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Ok(id))
+}
+
+// Challenges the caller sent or got, newest first
+pub async fn peer_challenges(headers: HeaderMap, State(state): State<AppState>, Query(query): Query<PeerChallengeQuery>) -> Result<Json<Vec<PeerChallenge>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as::<_, PeerChallenge>("SELECT * FROM (SELECT c.id, c.challenged_id = $1 AS incoming, public_name(u) AS name, public_avatar(u) AS avatar, \
+            c.quest_id, c.wheel_challenge_id, COALESCE(q.name, w.title) AS title, c.message, c.deadline, \
+            CASE WHEN c.status IN ('pending', 'accepted') AND c.deadline <= NOW() THEN 'expired' ELSE c.status END AS status, c.bonus_points, \
+            CASE WHEN c.challenger_id = $1 THEN c.challenger_done_at ELSE c.challenged_done_at END AS my_done_at, \
+            CASE WHEN c.challenger_id = $1 THEN c.challenged_done_at ELSE c.challenger_done_at END AS friend_done_at, c.created_at \
+        FROM peer_challenges c JOIN users u ON u.id = CASE WHEN c.challenger_id = $1 THEN c.challenged_id ELSE c.challenger_id END \
+        LEFT JOIN quests q ON q.id = c.quest_id LEFT JOIN wheel_challenges w ON w.id = c.wheel_challenge_id \
+        WHERE c.challenger_id = $1 OR c.challenged_id = $1) c \
+        WHERE $2::peer_challenge_status IS NULL OR c.status = $2 ORDER BY c.created_at DESC LIMIT $3;")
+        .bind(uid)
+        .bind(query.status)
+        .bind(CHALLENGE_HISTORY_SIZE)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(c) => Ok(Json(c)),
+        Err(e) => {
+            eprintln!("peer challenges: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn accept_peer_challenge(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("UPDATE peer_challenges SET status = 'accepted', responded_at = NOW()
+        WHERE id = $1 AND challenged_id = $2 AND status = 'pending' AND (deadline IS NULL OR deadline > NOW());", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("accept peer challenge: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn decline_peer_challenge(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("UPDATE peer_challenges SET status = 'declined', responded_at = NOW()
+        WHERE id = $1 AND challenged_id = $2 AND status = 'pending' AND (deadline IS NULL OR deadline > NOW());", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("decline peer challenge: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Marks a wheel challenge as done by the caller, like completing it on the wheel does.
+// Quests are completed the usual way and count through `quest_completed` (409 here).
+pub async fn complete_peer_challenge(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match complete_tx(&state, uid, id).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("complete peer challenge: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn complete_tx(state: &AppState, uid: Uuid, id: Uuid) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let challenge = sqlx::query!(r#"SELECT quest_id, CASE WHEN challenger_id = $2 THEN challenger_done_at ELSE challenged_done_at END AS done_at
+        FROM peer_challenges WHERE id = $1 AND (challenger_id = $2 OR challenged_id = $2) AND status = 'accepted'
+            AND (deadline IS NULL OR deadline > NOW()) FOR UPDATE;"#, id, uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+    match challenge {
+        None => return Ok(StatusCode::NOT_FOUND),
+        Some(c) if c.quest_id.is_some() || c.done_at.is_some() => return Ok(StatusCode::CONFLICT),
+        Some(_) => {}
+    }
+    let mut after = AfterCommit::default();
+    mark_done(&mut tx, state, &mut after, uid, Some(id), None, None).await?;
+    after.commit(tx, state).await?;
+    Ok(StatusCode::OK)
+}
+
+// Records that `uid` did their part of the accepted challenges matching `id`, `quest` or `wheel`.
+// Whoever finishes second completes the challenge and both get the bonus.
+async fn mark_done(tx: &mut PgConnection, state: &AppState, after: &mut AfterCommit, uid: Uuid, id: Option<Uuid>, quest: Option<Uuid>, wheel: Option<Uuid>) -> Result<(), sqlx::Error> {
+    let completed = sqlx::query!(r#"UPDATE peer_challenges SET
+            challenger_done_at = CASE WHEN challenger_id = $1 THEN COALESCE(challenger_done_at, NOW()) ELSE challenger_done_at END,
+            challenged_done_at = CASE WHEN challenged_id = $1 THEN COALESCE(challenged_done_at, NOW()) ELSE challenged_done_at END,
+            status = CASE WHEN (challenger_id = $1 OR challenger_done_at IS NOT NULL) AND (challenged_id = $1 OR challenged_done_at IS NOT NULL)
+                THEN 'completed' ELSE status END,
+            completed_at = CASE WHEN (challenger_id = $1 OR challenger_done_at IS NOT NULL) AND (challenged_id = $1 OR challenged_done_at IS NOT NULL)
+                THEN NOW() END
+        WHERE (challenger_id = $1 OR challenged_id = $1) AND status = 'accepted' AND (deadline IS NULL OR deadline > NOW())
+            AND (id = $2 OR quest_id = $3 OR wheel_challenge_id = $4)
+        RETURNING challenger_id, challenged_id, bonus_points, completed_at IS NOT NULL AS "completed!";"#, uid, id, quest, wheel)
+        .fetch_all(&mut *tx)
+        .await?;
+    for c in completed.into_iter().filter(|c| c.completed) {
+        award_points(tx, state, after, c.challenger_id, c.bonus_points).await?;
+        award_points(tx, state, after, c.challenged_id, c.bonus_points).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn quest_completed(tx: &mut PgConnection, state: &AppState, after: &mut AfterCommit, uid: Uuid, quest_id: Uuid) -> Result<(), sqlx::Error> {
+    mark_done(tx, state, after, uid, None, Some(quest_id), None).await
+}
+
+pub(crate) async fn wheel_completed(tx: &mut PgConnection, state: &AppState, after: &mut AfterCommit, uid: Uuid, challenge_id: Uuid) -> Result<(), sqlx::Error> {
+    mark_done(tx, state, after, uid, None, None, Some(challenge_id)).await
+}
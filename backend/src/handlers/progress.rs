@@ -0,0 +1,127 @@
+use axum::{Json, extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use chrono::Utc;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::data::{ActivityKind, AppState, CheckinResult, QuestProgress};
+use super::{AfterCommit, Availability, award_points, get_quest_history, is_repeat_violation, lock_user, quest_availability, quest_points, record_completion, update_streak};
+
+// Multi-step quests the user has started, with the step that comes next (if the quest has steps)
+pub(crate) async fn get_user_progress(db: impl PgExecutor<'_>, uid: Uuid) -> Result<Vec<QuestProgress>, sqlx::Error> {
+    sqlx::query_as!(QuestProgress, r#"SELECT p.quest_id, q.name, p.checkins, q.required_checkins, p.started_at, p.last_checkin,
+            s.name AS "next_step?", s.description AS "next_step_description?"
+        FROM quest_progress p JOIN quests q ON q.id = p.quest_id
+        LEFT JOIN quest_steps s ON s.quest_id = p.quest_id AND s.position = p.checkins + 1
+        WHERE p.user_id = $1 ORDER BY p.started_at;"#, uid)
+        .fetch_all(db)
+        .await
+}
+
+// Records one check-in of a multi-step quest. Only one check-in per day counts.
+// The last check-in completes the quest, the points are given either then or a share on every check-in (prorate_points).
+pub async fn checkin(headers: HeaderMap, State(state): State<AppState>, Path(qid): Path<Uuid>) -> Result<Json<CheckinResult>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match checkin_tx(&state, uid, qid).await {
+        Ok(Ok(result)) => {
+            let _ = update_streak(&state, &headers, ActivityKind::Quest, 1).await;
+            Ok(Json(result))
+        }
+        Ok(Err(status)) => Err(status),
+        Err(e) if is_repeat_violation(&e) => Err(StatusCode::CONFLICT),
+        Err(e) => {
+            eprintln!("checkin: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn checkin_tx(state: &AppState, uid: Uuid, qid: Uuid) -> Result<Result<CheckinResult, StatusCode>, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    lock_user(&mut tx, uid).await?;
+    let mut after = AfterCommit::default();
+
+    let quest = match get_quest_history(&mut *tx, uid, qid).await {
+        Ok(q) => q,
+        Err(sqlx::Error::RowNotFound) => return Ok(Err(StatusCode::NOT_FOUND)),
+        Err(e) => return Err(e),
+    };
+    if !quest.quest.self_complete {
+        return Ok(Err(StatusCode::FORBIDDEN));
+    }
+
+    let today = Utc::now().date_naive();
+    let progress = sqlx::query!("SELECT checkins, started_at, last_checkin FROM quest_progress WHERE user_id = $1 AND quest_id = $2;", uid, qid)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let (checkins, started_at) = match progress {
+        Some(p) if p.last_checkin == today => return Ok(Err(StatusCode::CONFLICT)),
+        Some(p) => (p.checkins + 1, p.started_at),
+        None => {
+            // Starting the quest (again) has to respect its repeat policy
+            if !matches!(quest_availability(&quest, today), Availability::Now) {
+                return Ok(Err(StatusCode::CONFLICT));
+            }
+            (1, Utc::now())
+        }
+    };
+
+    let required = quest.quest.required_checkins;
+    let total = quest_points(&quest);
+    // Rounded down per check-in so the shares always add up to the total
+    let share = if quest.quest.prorate_points {
+        total * checkins / required - total * (checkins - 1) / required
+    } else {
+        0
+    };
+
+    if checkins >= required {
+        let new_points = if quest.quest.prorate_points { share } else { total };
+        record_completion(&mut tx, state, &mut after, uid, &quest, total, new_points).await?;
+        sqlx::query!("DELETE FROM quest_progress WHERE user_id = $1 AND quest_id = $2;", uid, qid)
+            .execute(&mut *tx)
+            .await?;
+        after.commit(tx, state).await?;
+
+        return Ok(Ok(CheckinResult {
+            progress: QuestProgress {
+                quest_id: qid,
+                name: quest.quest.name,
+                checkins,
+                required_checkins: required,
+                started_at,
+                last_checkin: today,
+                next_step: None,
+                next_step_description: None,
+            },
+            completed: true,
+            points_awarded: new_points,
+        }));
+    }
+
+    sqlx::query!("INSERT INTO quest_progress (user_id, quest_id, checkins, started_at, last_checkin) VALUES ($1, $2, $3, $4, $5) \
+        ON CONFLICT (user_id, quest_id) DO UPDATE SET checkins = EXCLUDED.checkins, last_checkin = EXCLUDED.last_checkin;",
+        uid, qid, checkins, started_at, today)
+        .execute(&mut *tx)
+        .await?;
+    if share > 0 {
+        award_points(&mut tx, state, &mut after, uid, share).await?;
+    }
+
+    let progress = get_user_progress(&mut *tx, uid).await?
+        .into_iter()
+        .find(|p| p.quest_id == qid);
+    after.commit(tx, state).await?;
+    match progress {
+        Some(progress) => Ok(Ok(CheckinResult { progress, completed: false, points_awarded: share })),
+        None => Ok(Err(StatusCode::INTERNAL_SERVER_ERROR)),
+    }
+}
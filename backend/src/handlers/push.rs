@@ -0,0 +1,419 @@
+use std::time::Duration;
+
+use axum::{Json, body::Bytes, extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::Utc;
+use ring::{aead, agreement, error::Unspecified, hkdf, rand::{SecureRandom, SystemRandom}, signature::{self, EcdsaKeyPair, KeyPair}};
+use uuid::Uuid;
+
+use crate::data::{AppState, MockPushEndpoint, MockPushKeys, MockPushMessage, MockPushStatusInput, MockPushSubscription, Notification, PushSubscription, PushSubscriptionInput, VapidKey, WebPush};
+
+// How long push services keep a message for a device that is offline
+const TTL_SECS: u32 = 86400;
+// Record size of the aes128gcm encoding, the whole payload goes into one record
+const RECORD_SIZE: u32 = 4096;
+const MAX_ATTEMPTS: u32 = 3;
+// Doubles with every attempt unless the push service sends Retry-After
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+// Subscriptions that failed this many times in a row are dropped
+const MAX_FAILURES: i32 = 5;
+const MAX_ENDPOINT_LENGTH: usize = 2048;
+const USER_AGENT_LENGTH: usize = 255;
+
+// Both keys are base64url, the private key as the raw 32 byte scalar. That's how `web-push generate-vapid-keys` prints them.
+pub fn web_push(public_key: &str, private_key: &str, subject: String) -> Option<WebPush> {
+    let public = URL_SAFE_NO_PAD.decode(public_key.trim_end_matches('=')).ok()?;
+    let private = URL_SAFE_NO_PAD.decode(private_key.trim_end_matches('=')).ok()?;
+    let key_pair = EcdsaKeyPair::from_private_key_and_public_key(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &private, &public, &SystemRandom::new()).ok()?;
+    let public_key = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .ok()?;
+    Some(WebPush { key_pair, public_key, subject, client })
+}
+
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn hkdf(salt: &[u8], ikm: &[u8], info: &[&[u8]], len: usize) -> Result<Vec<u8>, Unspecified> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(ikm);
+    let mut out = vec![0; len];
+    prk.expand(info, Len(len))?.fill(&mut out)?;
+    Ok(out)
+}
+
+// Encrypts the payload for the device like RFC 8291 says: ECDH with a new key, the auth secret and a random salt
+// give the content key, the result is a single aes128gcm record (RFC 8188) with our public key as the key id.
+fn encrypt(p256dh: &[u8], auth: &[u8], payload: &[u8]) -> Result<Vec<u8>, Unspecified> {
+    if payload.len() + 17 > RECORD_SIZE as usize {
+        return Err(Unspecified);
+    }
+    let rng = SystemRandom::new();
+    let private = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)?;
+    let public = private.compute_public_key()?;
+    let ecdh = agreement::agree_ephemeral(private, &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, p256dh), |s| s.to_vec())?;
+
+    let ikm = hkdf(auth, &ecdh, &[b"WebPush: info\0", p256dh, public.as_ref()], 32)?;
+    let mut salt = [0u8; 16];
+    rng.fill(&mut salt)?;
+    let cek = hkdf(&salt, &ikm, &[b"Content-Encoding: aes128gcm\0"], 16)?;
+    let nonce = hkdf(&salt, &ikm, &[b"Content-Encoding: nonce\0"], 12)?;
+
+    // 0x02 marks the last record, no padding after it
+    let mut record = payload.to_vec();
+    record.push(2);
+    let key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, &cek)?);
+    key.seal_in_place_append_tag(aead::Nonce::try_assume_unique_for_key(&nonce)?, aead::Aad::empty(), &mut record)?;
+
+    let mut body = Vec::with_capacity(16 + 4 + 1 + public.as_ref().len() + record.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(public.as_ref().len() as u8);
+    body.extend_from_slice(public.as_ref());
+    body.extend_from_slice(&record);
+    Ok(body)
+}
+
+// VAPID (RFC 8292), a JWT for the push service's origin signed with our key
+fn vapid(push: &WebPush, endpoint: &reqwest::Url) -> Result<String, Unspecified> {
+    let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+    let claims = serde_json::json!({
+        "aud": endpoint.origin().ascii_serialization(),
+        "exp": Utc::now().timestamp() + 12 * 3600,
+        "sub": push.subject,
+    });
+    let unsigned = format!("{}.{}", header, URL_SAFE_NO_PAD.encode(claims.to_string()));
+    let sig = push.key_pair.sign(&SystemRandom::new(), unsigned.as_bytes())?;
+    Ok(format!("vapid t={}.{}, k={}", unsigned, URL_SAFE_NO_PAD.encode(sig.as_ref()), push.public_key))
+}
+
+enum Outcome {
+    Delivered,
+    // The push service doesn't know the subscription anymore, or it can never be delivered to
+    Gone,
+    Failed,
+}
+
+// Retries when the push service is busy or unreachable, other errors aren't going to go away by retrying
+async fn deliver(push: &WebPush, endpoint: &str, p256dh: &str, auth: &str, payload: &[u8]) -> Outcome {
+    let prepared = reqwest::Url::parse(endpoint).map_err(|_| Unspecified).and_then(|url| {
+        let p256dh = URL_SAFE_NO_PAD.decode(p256dh).map_err(|_| Unspecified)?;
+        let auth = URL_SAFE_NO_PAD.decode(auth).map_err(|_| Unspecified)?;
+        let body = encrypt(&p256dh, &auth, payload)?;
+        let authorization = vapid(push, &url)?;
+        Ok((url, body, authorization))
+    });
+    let Ok((url, body, authorization)) = prepared else {
+        eprintln!("push to {}: can't encrypt for this subscription", endpoint);
+        return Outcome::Gone;
+    };
+
+    for attempt in 0..MAX_ATTEMPTS {
+        let res = push.client.post(url.clone())
+            .header("TTL", TTL_SECS)
+            .header("Urgency", "normal")
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header("Authorization", &authorization)
+            .body(body.clone())
+            .send()
+            .await;
+        let retry_after = match res {
+            Ok(r) if r.status().is_success() => return Outcome::Delivered,
+            Ok(r) if matches!(r.status().as_u16(), 404 | 410) => return Outcome::Gone,
+            Ok(r) if r.status().as_u16() == 429 || r.status().is_server_error() => {
+                r.headers().get("Retry-After").and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()).map(Duration::from_secs)
+            }
+            Ok(r) => {
+                eprintln!("push to {}: {}", url.origin().ascii_serialization(), r.status());
+                return Outcome::Failed;
+            }
+            Err(e) => {
+                eprintln!("push to {}: {:?}", url.origin().ascii_serialization(), e);
+                None
+            }
+        };
+        if attempt + 1 < MAX_ATTEMPTS {
+            tokio::time::sleep(retry_after.unwrap_or(RETRY_DELAY * 2u32.pow(attempt)).min(MAX_RETRY_DELAY)).await;
+        }
+    }
+    Outcome::Failed
+}
+
+async fn send_to_user(state: &AppState, uid: Uuid, payload: &[u8]) -> Result<(), sqlx::Error> {
+    let Some(push) = &state.push else { return Ok(()) };
+    // Expired subscriptions are dropped instead of tried
+    sqlx::query!("DELETE FROM push_subscriptions WHERE user_id = $1 AND expires_at < NOW();", uid)
+        .execute(&state.db_connection)
+        .await?;
+    let subscriptions = sqlx::query!("SELECT id, endpoint, p256dh, auth FROM push_subscriptions WHERE user_id = $1;", uid)
+        .fetch_all(&state.db_connection)
+        .await?;
+
+    for s in subscriptions {
+        match deliver(push, &s.endpoint, &s.p256dh, &s.auth, payload).await {
+            Outcome::Delivered => {
+                sqlx::query!("UPDATE push_subscriptions SET failures = 0, last_success_at = NOW() WHERE id = $1;", s.id)
+                    .execute(&state.db_connection)
+                    .await?;
+            }
+            Outcome::Gone => {
+                sqlx::query!("DELETE FROM push_subscriptions WHERE id = $1;", s.id)
+                    .execute(&state.db_connection)
+                    .await?;
+            }
+            Outcome::Failed => {
+                let failures = sqlx::query_scalar!("UPDATE push_subscriptions SET failures = failures + 1 WHERE id = $1 RETURNING failures;", s.id)
+                    .fetch_optional(&state.db_connection)
+                    .await?;
+                if failures.is_some_and(|f| f >= MAX_FAILURES) {
+                    sqlx::query!("DELETE FROM push_subscriptions WHERE id = $1;", s.id)
+                        .execute(&state.db_connection)
+                        .await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sends the notification to the user's devices in the background, the caller doesn't wait for the push services
+pub(crate) fn push_notification(state: &AppState, uid: Uuid, notification: &Notification) {
+    if state.push.is_none() {
+        return;
+    }
+    let payload = match serde_json::to_vec(notification) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("push: {:?}", e);
+            return;
+        }
+    };
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = send_to_user(&state, uid, &payload).await {
+            eprintln!("push: {:?}", e);
+        }
+    });
+}
+
+// The key the frontend passes to pushManager.subscribe() as applicationServerKey. 503 when push isn't set up.
+pub async fn vapid_key(State(state): State<AppState>) -> Result<Json<VapidKey>, StatusCode> {
+    match &state.push {
+        Some(push) => Ok(Json(VapidKey { public_key: push.public_key.clone() })),
+        None => Err(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+// The backend posts to whatever endpoint is registered, so it can't point into our own network.
+// Push services are https on a domain name, the only other endpoints allowed are the mock's.
+fn allowed_endpoint(state: &AppState, url: &reqwest::Url) -> bool {
+    if let Some(mock) = &state.push_mock {
+        let id = url.as_str().strip_prefix(mock.url.as_str()).and_then(|rest| rest.strip_prefix('/'));
+        if id.is_some_and(|id| id.parse::<Uuid>().is_ok()) {
+            return true;
+        }
+    }
+    // IP addresses have no domain
+    let Some(host) = url.domain() else {
+        return false;
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    url.scheme() == "https"
+        && url.port().is_none()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && host.contains('.')
+        && !["localhost", "local", "internal", "home.arpa"].iter().any(|s| host == *s || host.ends_with(&format!(".{}", s)))
+}
+
+// Registers the browser's subscription and returns its id. A browser that subscribes again keeps its id,
+// also when someone else logged in on it.
+pub async fn register_subscription(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<PushSubscriptionInput>) -> Result<Json<Uuid>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    if state.push.is_none() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    let Ok(url) = reqwest::Url::parse(&body.endpoint) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    if body.endpoint.len() > MAX_ENDPOINT_LENGTH || !allowed_endpoint(&state, &url) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let p256dh = URL_SAFE_NO_PAD.decode(body.keys.p256dh.trim_end_matches('=')).unwrap_or_default();
+    let auth = URL_SAFE_NO_PAD.decode(body.keys.auth.trim_end_matches('=')).unwrap_or_default();
+    if p256dh.len() != 65 || p256dh[0] != 4 || auth.len() != 16 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let expires_at = match body.expiration_time {
+        Some(ms) => match chrono::DateTime::from_timestamp_millis(ms) {
+            Some(t) => Some(t),
+            None => return Err(StatusCode::BAD_REQUEST),
+        },
+        None => None,
+    };
+    let user_agent: String = headers.get("User-Agent").and_then(|u| u.to_str().ok()).unwrap_or("").chars().take(USER_AGENT_LENGTH).collect();
+
+    let res = sqlx::query_scalar!("INSERT INTO push_subscriptions (user_id, endpoint, p256dh, auth, user_agent, expires_at) VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (endpoint) DO UPDATE SET user_id = EXCLUDED.user_id, p256dh = EXCLUDED.p256dh, auth = EXCLUDED.auth,
+            user_agent = EXCLUDED.user_agent, expires_at = EXCLUDED.expires_at, failures = 0
+        RETURNING id;", uid, body.endpoint, URL_SAFE_NO_PAD.encode(&p256dh), URL_SAFE_NO_PAD.encode(&auth), user_agent, expires_at)
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(id) => Ok(Json(id)),
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("register push subscription: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn push_subscriptions(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<PushSubscription>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as!(PushSubscription, "SELECT id, user_agent, created_at, expires_at, last_success_at FROM push_subscriptions
+        WHERE user_id = $1 AND (expires_at IS NULL OR expires_at > NOW()) ORDER BY created_at DESC;", uid)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(s) => Ok(Json(s)),
+        Err(e) => {
+            eprintln!("push subscriptions: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn unregister_subscription(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query!("DELETE FROM push_subscriptions WHERE id = $1 AND user_id = $2;", id, uid)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("unregister push subscription: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// The mock push service stands in for the browsers' push services when PUSH_MOCK is on. It hands out
+// subscriptions with endpoints on this server and keeps what is pushed to them in memory.
+pub async fn mock_subscribe(State(state): State<AppState>) -> Result<Json<MockPushSubscription>, StatusCode> {
+    let Some(mock) = &state.push_mock else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    // Nothing is decrypted here, the key only has to be a valid point
+    let rng = SystemRandom::new();
+    let mut auth = [0u8; 16];
+    let public = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)
+        .and_then(|k| k.compute_public_key())
+        .and_then(|p| rng.fill(&mut auth).map(|_| p));
+    let public = match public {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("mock push: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let id = Uuid::new_v4();
+    mock.endpoints.lock().await.insert(id, MockPushEndpoint { status: StatusCode::CREATED.as_u16(), received: Vec::new() });
+    Ok(Json(MockPushSubscription {
+        endpoint: format!("{}/{}", mock.url, id),
+        keys: MockPushKeys { p256dh: URL_SAFE_NO_PAD.encode(public.as_ref()), auth: URL_SAFE_NO_PAD.encode(auth) },
+    }))
+}
+
+// Where the backend delivers to, answers with the endpoint's status. Unknown endpoints give 404 like a real push service.
+pub async fn mock_receive(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>, body: Bytes) -> StatusCode {
+    let Some(mock) = &state.push_mock else {
+        return StatusCode::NOT_FOUND;
+    };
+    let mut endpoints = mock.endpoints.lock().await;
+    let Some(endpoint) = endpoints.get_mut(&id) else {
+        return StatusCode::NOT_FOUND;
+    };
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok()).map(str::to_string);
+    endpoint.received.push(MockPushMessage {
+        ttl: header("TTL"),
+        urgency: header("Urgency"),
+        content_encoding: header("Content-Encoding"),
+        authorization: header("Authorization"),
+        body: URL_SAFE_NO_PAD.encode(&body),
+        received_at: Utc::now(),
+    });
+    StatusCode::from_u16(endpoint.status).unwrap_or(StatusCode::CREATED)
+}
+
+pub async fn mock_messages(Path(id): Path<Uuid>, State(state): State<AppState>) -> Result<Json<Vec<MockPushMessage>>, StatusCode> {
+    let Some(mock) = &state.push_mock else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    match mock.endpoints.lock().await.get(&id) {
+        Some(endpoint) => Ok(Json(endpoint.received.clone())),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Makes the endpoint answer with another status, like 410 for an unsubscribed browser or 503 for retries
+pub async fn mock_set_status(Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<MockPushStatusInput>) -> StatusCode {
+    let Some(mock) = &state.push_mock else {
+        return StatusCode::NOT_FOUND;
+    };
+    if StatusCode::from_u16(body.status).is_err() {
+        return StatusCode::BAD_REQUEST;
+    }
+    match mock.endpoints.lock().await.get_mut(&id) {
+        Some(endpoint) => {
+            endpoint.status = body.status;
+            StatusCode::OK
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+pub async fn mock_unsubscribe(Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let Some(mock) = &state.push_mock else {
+        return StatusCode::NOT_FOUND;
+    };
+    match mock.endpoints.lock().await.remove(&id) {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::NOT_FOUND,
+    }
+}
@@ -0,0 +1,111 @@
+use axum::{Json, extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use uuid::Uuid;
+
+use crate::data::{AppState, FeedAction, ReactionCount, ReactionKind};
+use super::feed;
+
+// Reactions a user can give per minute, across all events
+const REACTIONS_PER_MINUTE: i64 = 30;
+
+// How often each reaction was given to the event, only the ones given at least once
+pub async fn reactions(headers: HeaderMap, Path(id): Path<Uuid>, State(state): State<AppState>) -> Result<Json<Vec<ReactionCount>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match feed::can_see(&state, uid, id).await {
+        Ok(true) => {}
+        Ok(false) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            eprintln!("reactions: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let res = sqlx::query_as::<_, ReactionCount>("SELECT reaction, COUNT(*) AS count, bool_or(user_id = $2) AS mine FROM event_reactions \
+        WHERE event_id = $1 GROUP BY reaction ORDER BY reaction;")
+        .bind(id)
+        .bind(uid)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) => Ok(Json(r)),
+        Err(e) => {
+            eprintln!("reactions: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Giving the same reaction twice is fine, it's only counted once
+pub async fn add_reaction(headers: HeaderMap, Path((id, reaction)): Path<(Uuid, ReactionKind)>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match react(&state, uid, id, reaction).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("add reaction: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn react(state: &AppState, uid: Uuid, id: Uuid, reaction: ReactionKind) -> Result<StatusCode, sqlx::Error> {
+    if !feed::can_see(state, uid, id).await? {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    let mut tx = state.db_connection.begin().await?;
+    let res = sqlx::query("INSERT INTO event_reactions (event_id, user_id, reaction) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING;")
+        .bind(id)
+        .bind(uid)
+        .bind(reaction)
+        .execute(&mut *tx)
+        .await?;
+    // Giving a reaction that is already there doesn't count
+    if res.rows_affected() == 0 {
+        return Ok(StatusCode::OK);
+    }
+    if !feed::rate_limit(&mut tx, uid, FeedAction::Reaction, REACTIONS_PER_MINUTE, "1 minute").await? {
+        return Ok(StatusCode::TOO_MANY_REQUESTS);
+    }
+    tx.commit().await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn remove_reaction(headers: HeaderMap, Path((id, reaction)): Path<(Uuid, ReactionKind)>, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    let res = sqlx::query("DELETE FROM event_reactions WHERE event_id = $1 AND user_id = $2 AND reaction = $3;")
+        .bind(id)
+        .bind(uid)
+        .bind(reaction)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("remove reaction: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::{future::Future, pin::Pin, time::Duration};
+
+use axum::{Json, extract::State, http::{HeaderMap, StatusCode}};
+use uuid::Uuid;
+
+use crate::data::{AppState, NotificationKind, Reminder, ReminderKind, ReminderSettings, ReminderSettingsInput};
+use super::notifications;
+
+// Users who are due a reminder of the kind, with the day it counts for in their time zone.
+// Weekly challenge and goal reminders go out from 09:00, the others at the time the user picked.
+// Streak days are in UTC like in streaks.rs, a streak is at risk on the last day freezes can still cover.
+const DUE_QUERIES: [(ReminderKind, &str); 4] = [
+    (ReminderKind::Questionnaire, "SELECT u.id AS user_id, u.mail, r.email, (NOW() AT TIME ZONE r.timezone)::date AS day, 0::bigint AS amount \
+        FROM users u JOIN reminder_preferences r ON r.user_id = u.id \
+        WHERE NOT u.banned AND r.questionnaire AND (NOW() AT TIME ZONE r.timezone)::time >= r.questionnaire_time \
+            AND NOT EXISTS (SELECT 1 FROM questionnaire_scores s WHERE s.user_id = u.id \
+                AND (s.answered_at AT TIME ZONE r.timezone)::date = (NOW() AT TIME ZONE r.timezone)::date)"),
+    (ReminderKind::StreakAtRisk, "SELECT u.id AS user_id, u.mail, r.email, (NOW() AT TIME ZONE r.timezone)::date AS day, u.current_streak::bigint AS amount \
+        FROM users u JOIN reminder_preferences r ON r.user_id = u.id \
+        WHERE NOT u.banned AND r.streak_at_risk AND (NOW() AT TIME ZONE r.timezone)::time >= r.streak_warning_time \
+            AND u.current_streak > 0 AND u.last_active = (NOW() AT TIME ZONE 'UTC')::date - 1 - u.streak_freezes"),
+    (ReminderKind::WeeklyChallenge, "SELECT u.id AS user_id, u.mail, r.email, date_trunc('week', NOW() AT TIME ZONE r.timezone)::date AS day, 0::bigint AS amount \
+        FROM users u JOIN reminder_preferences r ON r.user_id = u.id \
+        WHERE NOT u.banned AND r.weekly_challenge AND (NOW() AT TIME ZONE r.timezone)::time >= '09:00' \
+            AND (u.completed_weekly IS NULL OR u.completed_weekly < date_trunc('week', NOW() AT TIME ZONE r.timezone)::date)"),
+    (ReminderKind::GoalDue, "SELECT u.id AS user_id, u.mail, r.email, (NOW() AT TIME ZONE r.timezone)::date AS day, g.amount \
+        FROM users u JOIN reminder_preferences r ON r.user_id = u.id \
+        CROSS JOIN LATERAL (SELECT COUNT(*) AS amount FROM personal_challanges g \
+            WHERE g.user_id = u.id AND g.status = 'active' AND g.due_date = (NOW() AT TIME ZONE r.timezone)::date) g \
+        WHERE NOT u.banned AND r.goal_due AND (NOW() AT TIME ZONE r.timezone)::time >= '09:00' AND g.amount > 0"),
+];
+
+// Delivers a reminder one way. Boxed futures so the scheduler can hold a list of different channels.
+pub trait NotificationChannel: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn deliver<'a>(&'a self, state: &'a AppState, reminder: &'a Reminder) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + 'a>>;
+}
+
+// Shows up in the notification center and on /api/events
+pub struct InAppChannel;
+
+impl NotificationChannel for InAppChannel {
+    fn name(&self) -> &'static str {
+        "in_app"
+    }
+
+    fn deliver<'a>(&'a self, state: &'a AppState, reminder: &'a Reminder) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let notification = notifications::emit(&state.db_connection, reminder.user_id, NotificationKind::Reminder, &reminder.title, &reminder.body, None).await?;
+            if let Some(n) = notification {
+                notifications::deliver(state, reminder.user_id, n);
+            }
+            Ok(())
+        })
+    }
+}
+
+// Queues the mail in email_outbox for users who turned email reminders on
+pub struct EmailChannel;
+
+impl NotificationChannel for EmailChannel {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn deliver<'a>(&'a self, state: &'a AppState, reminder: &'a Reminder) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let Some(mail) = &reminder.mail else { return Ok(()) };
+            sqlx::query!("INSERT INTO email_outbox (recipient, subject, body) VALUES ($1, $2, $3);", mail, reminder.title, reminder.body)
+                .execute(&state.db_connection)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+// Only prints the reminder, for trying the scheduler out without bothering anyone
+pub struct LogChannel;
+
+impl NotificationChannel for LogChannel {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn deliver<'a>(&'a self, _state: &'a AppState, reminder: &'a Reminder) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + 'a>> {
+        println!("reminder {:?} for {}: {} - {}", reminder.kind, reminder.user_id, reminder.title, reminder.body);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// The channels from a comma separated list of their names, unknown names are skipped
+pub fn channels(names: &str) -> Vec<Box<dyn NotificationChannel>> {
+    names.split(',').map(str::trim).filter(|n| !n.is_empty()).filter_map(|n| match n {
+        "in_app" => Some(Box::new(InAppChannel) as Box<dyn NotificationChannel>),
+        "email" => Some(Box::new(EmailChannel)),
+        "log" => Some(Box::new(LogChannel)),
+        _ => {
+            eprintln!("unknown reminder channel {}", n);
+            None
+        }
+    }).collect()
+}
+
+fn text(kind: ReminderKind, amount: i64) -> (String, String) {
+    match kind {
+        ReminderKind::Questionnaire => ("Време за въпросника".to_string(), "Отдели минута да отбележиш как си днес.".to_string()),
+        ReminderKind::StreakAtRisk => ("Серията ти е в опасност".to_string(), format!("Направи нещо днес, за да запазиш серията си от {} дни.", amount)),
+        ReminderKind::WeeklyChallenge => ("Седмичното предизвикателство те чака".to_string(), "Тази седмица има ново предизвикателство за теб.".to_string()),
+        ReminderKind::GoalDue => ("Цели със срок днес".to_string(), format!("Имаш {} цели със срок днес.", amount)),
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct Due {
+    user_id: Uuid,
+    mail: Option<String>,
+    amount: i64,
+}
+
+// Sends the reminders that became due since the last run. They are marked as sent before they go out,
+// so a reminder that fails on a channel isn't sent again.
+async fn send_due(state: &AppState, channels: &[Box<dyn NotificationChannel>]) -> Result<(), sqlx::Error> {
+    for (kind, query) in DUE_QUERIES {
+        let due = sqlx::query_as::<_, Due>(&format!("WITH due AS ({}), \
+            sent AS (INSERT INTO reminders_sent (user_id, kind, day) SELECT user_id, $1, day FROM due ON CONFLICT DO NOTHING RETURNING user_id) \
+            SELECT due.user_id, CASE WHEN due.email THEN due.mail END AS mail, due.amount FROM due JOIN sent ON sent.user_id = due.user_id;", query))
+            .bind(kind)
+            .fetch_all(&state.db_connection)
+            .await?;
+        for d in due {
+            let (title, body) = text(kind, d.amount);
+            let reminder = Reminder { user_id: d.user_id, kind, mail: d.mail, title, body };
+            for channel in channels {
+                if let Err(e) = channel.deliver(state, &reminder).await {
+                    eprintln!("reminder over {}: {:?}", channel.name(), e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Runs for as long as the server does
+pub async fn scheduler(state: AppState, channels: Vec<Box<dyn NotificationChannel>>, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        if let Err(e) = send_due(&state, &channels).await {
+            eprintln!("reminders: {:?}", e);
+        }
+    }
+}
+
+pub async fn get_reminder_settings(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<ReminderSettings>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as!(ReminderSettings, "SELECT timezone, questionnaire, questionnaire_time, streak_at_risk, streak_warning_time, weekly_challenge, goal_due, email
+        FROM reminder_preferences WHERE user_id = $1;", uid)
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(s) => Ok(Json(s)),
+        Err(sqlx::Error::RowNotFound) => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("reminder settings: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Time zones Postgres doesn't know give 400
+pub async fn update_reminder_settings(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<ReminderSettingsInput>) -> Result<Json<ReminderSettings>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    if let Some(tz) = &body.timezone {
+        let known = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1) AS "known!";"#, tz)
+            .fetch_one(&state.db_connection)
+            .await;
+        match known {
+            Ok(true) => {}
+            Ok(false) => return Err(StatusCode::BAD_REQUEST),
+            Err(e) => {
+                eprintln!("reminder settings: {:?}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    let res = sqlx::query_as!(ReminderSettings, "UPDATE reminder_preferences SET timezone = COALESCE($2, timezone),
+            questionnaire = COALESCE($3, questionnaire), questionnaire_time = COALESCE($4, questionnaire_time),
+            streak_at_risk = COALESCE($5, streak_at_risk), streak_warning_time = COALESCE($6, streak_warning_time),
+            weekly_challenge = COALESCE($7, weekly_challenge), goal_due = COALESCE($8, goal_due), email = COALESCE($9, email)
+        WHERE user_id = $1
+        RETURNING timezone, questionnaire, questionnaire_time, streak_at_risk, streak_warning_time, weekly_challenge, goal_due, email;",
+        uid, body.timezone, body.questionnaire, body.questionnaire_time, body.streak_at_risk, body.streak_warning_time,
+        body.weekly_challenge, body.goal_due, body.email)
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(s) => Ok(Json(s)),
+        Err(sqlx::Error::RowNotFound) => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("reminder settings: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use axum::{Json, extract::State, http::{HeaderMap, StatusCode}};
+use uuid::Uuid;
+
+use crate::data::{AppState, PrivacySettings, PrivacySettingsInput};
+use super::leaderboard;
+
+// The avatars users can pick from, the frontend has an image for each
+const AVATARS: [&str; 8] = ["fox", "owl", "bear", "cat", "deer", "turtle", "bee", "whale"];
+const DISPLAY_NAME_LENGTH: std::ops::RangeInclusive<usize> = 3..=50;
+
+pub async fn avatars() -> Json<Vec<&'static str>> {
+    Json(AVATARS.to_vec())
+}
+
+pub async fn get_privacy(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<PrivacySettings>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as!(PrivacySettings, r#"SELECT show_on_leaderboard, anonymous, friends_only, display_name, avatar, alias, public_name(u) AS "public_name!"
+        FROM users u WHERE id = $1;"#, uid)
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(s) => Ok(Json(s)),
+        Err(sqlx::Error::RowNotFound) => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("privacy settings: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// Only the sent settings change. Display names can't be taken by someone else's display name or login name (409).
+pub async fn update_privacy(headers: HeaderMap, State(state): State<AppState>, Json(body): Json<PrivacySettingsInput>) -> Result<Json<PrivacySettings>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let display_name = body.display_name.as_deref().map(str::trim).map(|n| (!n.is_empty()).then_some(n));
+    if let Some(Some(name)) = display_name {
+        if !DISPLAY_NAME_LENGTH.contains(&name.chars().count()) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let taken = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM users WHERE id <> $1 AND (lower(name) = lower($2) OR lower(display_name) = lower($2))) AS "taken!";"#,
+            uid, name)
+            .fetch_one(&state.db_connection)
+            .await;
+        match taken {
+            Ok(false) => {}
+            Ok(true) => return Err(StatusCode::CONFLICT),
+            Err(e) => {
+                eprintln!("privacy settings: {:?}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    let avatar = body.avatar.as_deref().map(|a| (!a.is_empty()).then_some(a));
+    if let Some(Some(a)) = avatar && !AVATARS.contains(&a) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let res = sqlx::query_as!(PrivacySettings, r#"UPDATE users u SET show_on_leaderboard = COALESCE($2, show_on_leaderboard),
+            anonymous = COALESCE($3, anonymous), friends_only = COALESCE($4, friends_only),
+            display_name = CASE WHEN $5 THEN $6 ELSE display_name END, avatar = CASE WHEN $7 THEN $8 ELSE avatar END
+        WHERE id = $1
+        RETURNING show_on_leaderboard, anonymous, friends_only, display_name, avatar, alias, public_name(u) AS "public_name!";"#,
+        uid, body.show_on_leaderboard, body.anonymous, body.friends_only,
+        display_name.is_some(), display_name.flatten(), avatar.is_some(), avatar.flatten())
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(s) => {
+            leaderboard::invalidate(&state).await;
+            Ok(Json(s))
+        }
+        Err(sqlx::Error::RowNotFound) => Err(StatusCode::UNAUTHORIZED),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(StatusCode::CONFLICT),
+        Err(e) => {
+            eprintln!("privacy settings: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
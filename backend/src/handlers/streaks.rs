@@ -0,0 +1,368 @@
+use axum::{Json, extract::{Path, Query, State}, http::{HeaderMap, StatusCode}};
+use chrono::{Datelike, Days, Months, NaiveDate, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use super::{AfterCommit, award_points, events, feed, lock_user};
+use crate::data::{ActivityKind, ActivityRule, ActivityRuleInput, AppState, CalendarDay, CalendarQuery, LiveEvent, StreakCalendar, StreakStatus};
+
+// A freeze is earned every time the streak reaches a multiple of this
+const FREEZE_EVERY_DAYS: i32 = 7;
+const MAX_STREAK_FREEZES: i32 = 3;
+// How many times a user can repair a missed day in REPAIR_WINDOW_DAYS
+const REPAIRS_PER_WINDOW: i64 = 1;
+const REPAIR_WINDOW_DAYS: i32 = 30;
+// Streak lengths that get posted to the activity feed
+const STREAK_MILESTONES: [i32; 7] = [3, 7, 14, 30, 60, 100, 365];
+
+struct Streak {
+    current_streak: i32,
+    longest_streak: i32,
+    last_active: Option<NaiveDate>,
+}
+
+struct StreakRow {
+    // The length of the latest run of days, whether or not it's still going
+    current_streak: i32,
+    longest_streak: i32,
+    last_active: Option<NaiveDate>,
+    streak_freezes: i32,
+}
+
+// Derives the streaks from activity_days. A streak is a run of consecutive days that were either
+// active or kept by a freeze/repair, only the active days count towards its length.
+async fn derive_streak(tx: &mut PgConnection, uid: Uuid) -> Result<Streak, sqlx::Error> {
+    sqlx::query_as!(Streak, r#"WITH days AS (
+            SELECT day, bool_or(active) AS active FROM (
+                SELECT day, true AS active FROM activity_days WHERE user_id = $1
+                UNION ALL SELECT day, false FROM streak_freeze_days WHERE user_id = $1) d
+            GROUP BY day),
+        runs AS (
+            SELECT MAX(day) AS last, COUNT(*) FILTER (WHERE active) AS len
+            FROM (SELECT day, active, day - (ROW_NUMBER() OVER (ORDER BY day))::integer AS grp FROM days) r
+            GROUP BY grp)
+        SELECT COALESCE((SELECT len FROM runs ORDER BY last DESC LIMIT 1), 0)::integer AS "current_streak!",
+            COALESCE((SELECT MAX(len) FROM runs), 0)::integer AS "longest_streak!",
+            (SELECT MAX(last) FROM runs) AS last_active;"#, uid)
+        .fetch_one(tx)
+        .await
+}
+
+async fn streak_row(db: &mut PgConnection, uid: Uuid) -> Result<StreakRow, sqlx::Error> {
+    let user = sqlx::query!("SELECT longest_streak, streak_freezes FROM users WHERE id = $1;", uid)
+        .fetch_one(&mut *db)
+        .await?;
+    let streak = derive_streak(db, uid).await?;
+    // Streaks from before activity_days existed are only in the users table
+    Ok(StreakRow {
+        current_streak: streak.current_streak,
+        longest_streak: streak.longest_streak.max(user.longest_streak),
+        last_active: streak.last_active,
+        streak_freezes: user.streak_freezes,
+    })
+}
+
+// Covers the days missed since the last activity with freezes on `row` and returns the first day that needed one.
+// It's all or nothing, a streak that can't be saved keeps its freezes.
+fn cover_missed(row: &mut StreakRow, today: NaiveDate) -> Option<NaiveDate> {
+    let yesterday = today - Days::new(1);
+    let last_active = match row.last_active {
+        Some(d) if d < yesterday && row.current_streak > 0 => d,
+        _ => return None,
+    };
+    let missed = (yesterday - last_active).num_days() as i32;
+    if missed > row.streak_freezes {
+        return None;
+    }
+    row.streak_freezes -= missed;
+    row.last_active = Some(yesterday);
+    Some(last_active + Days::new(1))
+}
+
+// Locks the user and spends the freezes that cover the days missed since their last activity
+async fn settle(tx: &mut PgConnection, uid: Uuid, today: NaiveDate) -> Result<StreakRow, sqlx::Error> {
+    lock_user(tx, uid).await?;
+    let mut row = streak_row(tx, uid).await?;
+    let Some(from) = cover_missed(&mut row, today) else {
+        return Ok(row);
+    };
+
+    let yesterday = today - Days::new(1);
+    let missed = (yesterday - from).num_days() as i32 + 1;
+    sqlx::query!("INSERT INTO streak_freeze_days (user_id, day) SELECT $1, d::date FROM generate_series($2::date, $3::date, '1 day') d;",
+        uid, from, yesterday)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE users SET streak_freezes = streak_freezes - $2, last_active = $3 WHERE id = $1;", uid, missed, yesterday)
+        .execute(&mut *tx)
+        .await?;
+    Ok(row)
+}
+
+// Whether an activity of `kind` worth `amount` counts towards the streak, see activity_rules
+pub(crate) async fn qualifies(state: &AppState, kind: ActivityKind, amount: i32) -> Result<bool, sqlx::Error> {
+    let rule = sqlx::query_as::<_, ActivityRule>("SELECT * FROM activity_rules WHERE kind = $1;")
+        .bind(kind)
+        .fetch_optional(&state.db_connection)
+        .await?;
+    Ok(rule.is_some_and(|r| r.enabled && amount >= r.min_amount))
+}
+
+// Records the activity for today and returns the streak. The counters on users are kept as a cache of activity_days.
+pub(crate) async fn record_activity(state: &AppState, uid: Uuid, kind: ActivityKind) -> Result<i32, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let today = Utc::now().date_naive();
+    let row = settle(&mut tx, uid, today).await?;
+
+    sqlx::query("INSERT INTO activity_days (user_id, day, kind) VALUES ($1, $2, $3) \
+        ON CONFLICT (user_id, day, kind) DO UPDATE SET count = activity_days.count + 1;")
+        .bind(uid)
+        .bind(today)
+        .bind(kind)
+        .execute(&mut *tx)
+        .await?;
+    if row.last_active == Some(today) {
+        tx.commit().await?;
+        return Ok(row.current_streak);
+    }
+
+    let streak = derive_streak(&mut tx, uid).await?.current_streak;
+    let earned = if streak % FREEZE_EVERY_DAYS == 0 { 1 } else { 0 };
+    sqlx::query!("UPDATE users SET current_streak = $2, longest_streak = GREATEST(longest_streak, $2), last_active = $3, \
+        streak_freezes = LEAST(streak_freezes + $4, $5) WHERE id = $1;", uid, streak, today, earned, MAX_STREAK_FREEZES)
+        .execute(&mut *tx)
+        .await?;
+    if STREAK_MILESTONES.contains(&streak) {
+        feed::streak_milestone(&mut tx, uid, streak).await?;
+    }
+    tx.commit().await?;
+    events::publish(state, uid, LiveEvent::Streak { current_streak: streak });
+    Ok(streak)
+}
+
+async fn repairs_used(tx: &mut PgConnection, uid: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM streak_freeze_days WHERE user_id = $1 AND repaired AND created_at > NOW() - make_interval(days => $2);"#,
+        uid, REPAIR_WINDOW_DAYS)
+        .fetch_one(tx)
+        .await
+}
+
+// Only yesterday can be repaired, and only when the streak broke on it
+fn repairable(row: &StreakRow, today: NaiveDate) -> bool {
+    row.current_streak > 0 && row.last_active == Some(today - Days::new(2))
+}
+
+// Shows the streak as if the freezes that would cover missed days were spent, they are only really spent
+// when the user is active again or repairs their streak.
+pub(crate) async fn streak_status(state: &AppState, uid: Uuid) -> Result<StreakStatus, sqlx::Error> {
+    let mut conn = state.db_connection.acquire().await?;
+    let today = Utc::now().date_naive();
+    let mut row = streak_row(&mut conn, uid).await?;
+    let frozen = match cover_missed(&mut row, today) {
+        Some(from) => (today - from).num_days(),
+        None => 0,
+    };
+    let freezes_used = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM streak_freeze_days WHERE user_id = $1 AND NOT repaired;"#, uid)
+        .fetch_one(&mut *conn)
+        .await? + frozen;
+    let repairs_left = (REPAIRS_PER_WINDOW - repairs_used(&mut conn, uid).await?).max(0);
+
+    let current_streak = match row.last_active {
+        Some(d) if d >= today - Days::new(1) => row.current_streak,
+        _ => 0,
+    };
+    Ok(StreakStatus {
+        current_streak,
+        longest_streak: row.longest_streak,
+        last_completed_date: row.last_active,
+        freezes_available: row.streak_freezes,
+        freezes_used,
+        repairs_left,
+        can_repair: repairs_left > 0 && repairable(&row, today),
+    })
+}
+
+// Buys a streak freeze with points
+pub async fn buy_streak_freeze(headers: HeaderMap, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match buy_tx(&state, uid).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("streak freeze: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn buy_tx(state: &AppState, uid: Uuid) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let user = sqlx::query!("SELECT points, streak_freezes FROM users WHERE id = $1 FOR UPDATE;", uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let user = match user {
+        Some(u) => u,
+        None => return Ok(StatusCode::UNAUTHORIZED),
+    };
+    if user.streak_freezes >= MAX_STREAK_FREEZES {
+        return Ok(StatusCode::CONFLICT);
+    }
+    if user.points < state.streak_freeze_price {
+        return Ok(StatusCode::PAYMENT_REQUIRED);
+    }
+
+    sqlx::query!("UPDATE users SET streak_freezes = streak_freezes + 1 WHERE id = $1;", uid)
+        .execute(&mut *tx)
+        .await?;
+    let mut after = AfterCommit::default();
+    award_points(&mut tx, state, &mut after, uid, -state.streak_freeze_price).await?;
+    after.commit(tx, state).await?;
+    Ok(StatusCode::OK)
+}
+
+// Saves a streak that broke yesterday, a limited number of times
+pub async fn repair_streak(headers: HeaderMap, State(state): State<AppState>) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match repair_tx(&state, uid).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("streak repair: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn repair_tx(state: &AppState, uid: Uuid) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let today = Utc::now().date_naive();
+    let row = settle(&mut tx, uid, today).await?;
+    if !repairable(&row, today) {
+        return Ok(StatusCode::CONFLICT);
+    }
+    if repairs_used(&mut tx, uid).await? >= REPAIRS_PER_WINDOW {
+        return Ok(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    let yesterday = today - Days::new(1);
+    sqlx::query!("INSERT INTO streak_freeze_days (user_id, day, repaired) VALUES ($1, $2, true);", uid, yesterday)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE users SET last_active = $2 WHERE id = $1;", uid, yesterday)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_streak(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<StreakStatus>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    match streak_status(&state, uid).await {
+        Ok(s) => Ok(Json(s)),
+        Err(sqlx::Error::RowNotFound) => Err(StatusCode::UNAUTHORIZED),
+        Err(e) => {
+            eprintln!("streak: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// The caller's active and frozen days in `month` (YYYY-MM, this month by default)
+pub async fn streak_calendar(headers: HeaderMap, State(state): State<AppState>, Query(query): Query<CalendarQuery>) -> Result<Json<StreakCalendar>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let first = match query.month {
+        Some(m) => match NaiveDate::parse_from_str(&format!("{}-01", m), "%Y-%m-%d") {
+            Ok(d) => d,
+            Err(_) => return Err(StatusCode::BAD_REQUEST),
+        },
+        None => Utc::now().date_naive().with_day(1).unwrap(),
+    };
+    let last = match first.checked_add_months(Months::new(1)) {
+        Some(d) => d - Days::new(1),
+        None => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let days = sqlx::query_as::<_, CalendarDay>("SELECT d.day, COALESCE(a.kinds, '{}') AS kinds, COALESCE(a.activities, 0) AS activities, f.day IS NOT NULL AS frozen \
+        FROM (SELECT day FROM activity_days WHERE user_id = $1 AND day BETWEEN $2 AND $3 \
+            UNION SELECT day FROM streak_freeze_days WHERE user_id = $1 AND day BETWEEN $2 AND $3) d \
+        LEFT JOIN (SELECT day, array_agg(kind ORDER BY kind) AS kinds, SUM(count)::bigint AS activities FROM activity_days \
+            WHERE user_id = $1 AND day BETWEEN $2 AND $3 GROUP BY day) a ON a.day = d.day \
+        LEFT JOIN streak_freeze_days f ON f.user_id = $1 AND f.day = d.day \
+        ORDER BY d.day;")
+        .bind(uid)
+        .bind(first)
+        .bind(last)
+        .fetch_all(&state.db_connection)
+        .await;
+    match days {
+        Ok(days) => Ok(Json(StreakCalendar { month: first.format("%Y-%m").to_string(), days })),
+        Err(e) => {
+            eprintln!("streak calendar: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn admin_activity_rules(State(state): State<AppState>) -> Result<Json<Vec<ActivityRule>>, StatusCode> {
+    let res = sqlx::query_as::<_, ActivityRule>("SELECT * FROM activity_rules ORDER BY kind;")
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) => Ok(Json(r)),
+        Err(e) => {
+            eprintln!("activity rules: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn admin_edit_activity_rule(Path(kind): Path<ActivityKind>, State(state): State<AppState>, Json(body): Json<ActivityRuleInput>) -> StatusCode {
+    if body.min_amount.is_some_and(|m| m < 0) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let res = sqlx::query("INSERT INTO activity_rules (kind, enabled, min_amount) VALUES ($1, COALESCE($2, true), COALESCE($3, 1)) \
+        ON CONFLICT (kind) DO UPDATE SET enabled = COALESCE($2, activity_rules.enabled), min_amount = COALESCE($3, activity_rules.min_amount), updated_at = NOW();")
+        .bind(kind)
+        .bind(body.enabled)
+        .bind(body.min_amount)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            eprintln!("activity rules: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use axum::{Json, extract::{Path, State}, http::{HeaderMap, StatusCode}};
+use rand::Rng;
+use uuid::Uuid;
+
+use crate::data::{ActivityKind, AppState, ChallengeCategory, SpinResult, SpinState, WheelCache, WheelChallenge, WheelChallengeInput, WheelSegment, WheelSpin};
+use super::{AfterCommit, award_points, lock_user, peer_challenges, update_streak};
+
+// Changes made straight in the database show up after this long, admin changes right away
+const WHEEL_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+const SPIN_HISTORY_SIZE: i64 = 50;
+// Challenges from this many of the user's last spins are left out of the draw
+const RECENT_SPINS_EXCLUDED: i64 = 5;
+// Questionnaire answers older than this don't change the draw
+const QUESTIONNAIRE_MAX_AGE_DAYS: i32 = 7;
+const QUESTIONNAIRE_MAX_SCORE: f64 = 4.0;
+
+// The challenges on the wheel, in the order of the segments
+async fn get_enabled_challenges(state: &AppState) -> Result<Vec<WheelChallenge>, sqlx::Error> {
+    let mut cache = state.wheel_challenges.lock().await;
+    if let Some(c) = cache.as_ref() && c.loaded.elapsed() < WHEEL_CACHE_TTL {
+        return Ok(c.challenges.clone());
+    }
+
+    let challenges = sqlx::query_as::<_, WheelChallenge>("SELECT * FROM wheel_challenges WHERE enabled ORDER BY position, title;")
+        .fetch_all(&state.db_connection)
+        .await?;
+    *cache = Some(WheelCache { loaded: Instant::now(), challenges: challenges.clone() });
+    Ok(challenges)
+}
+
+async fn invalidate_cache(state: &AppState) {
+    *state.wheel_challenges.lock().await = None;
+}
+
+// Spins the wheel for the caller. The server picks the segment and stores the spin,
+// a user gets a limited number of spins per day and has to finish or skip the previous one first.
+// The draw is weighted, see `segment_weights` and `pick_segment`.
+pub async fn wheel_spin(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<SpinResult>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let challenges = match get_enabled_challenges(&state).await {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if challenges.is_empty() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    match spin_tx(&state, uid, &challenges).await {
+        Ok(Ok(res)) => Ok(Json(res)),
+        Ok(Err(status)) => Err(status),
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn spin_tx(state: &AppState, uid: Uuid, challenges: &[WheelChallenge]) -> Result<Result<SpinResult, StatusCode>, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    lock_user(&mut tx, uid).await?;
+
+    let spins_today = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM wheel_spins WHERE user_id = $1 AND spun_at >= date_trunc('day', NOW());"#, uid)
+        .fetch_one(&mut *tx)
+        .await?;
+    if spins_today >= state.wheel_spins_per_day {
+        return Ok(Err(StatusCode::TOO_MANY_REQUESTS));
+    }
+    let open = sqlx::query_scalar!("SELECT id FROM wheel_spins WHERE user_id = $1 AND state IN ('spun', 'accepted');", uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if open.is_some() {
+        return Ok(Err(StatusCode::CONFLICT));
+    }
+
+    let scores: HashMap<ChallengeCategory, i32> = sqlx::query_as::<_, (ChallengeCategory, i32)>("SELECT DISTINCT ON (category) category, score FROM questionnaire_scores \
+        WHERE user_id = $1 AND answered_at > NOW() - make_interval(days => $2) ORDER BY category, answered_at DESC;")
+        .bind(uid)
+        .bind(QUESTIONNAIRE_MAX_AGE_DAYS)
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+    let recent = sqlx::query_scalar!(r#"SELECT challenge_id AS "challenge_id!" FROM wheel_spins WHERE user_id = $1 AND challenge_id IS NOT NULL ORDER BY spun_at DESC LIMIT $2;"#,
+        uid, RECENT_SPINS_EXCLUDED)
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let weights = segment_weights(challenges, &scores, &recent);
+    let seed: u32 = rand::rng().random();
+    let index = pick_segment(&weights, seed);
+    let challenge = &challenges[index];
+    let spin = sqlx::query_as::<_, WheelSpin>("INSERT INTO wheel_spins (user_id, challenge_id, title, seed) VALUES ($1, $2, $3, $4) RETURNING *;")
+        .bind(uid)
+        .bind(challenge.id)
+        .bind(&challenge.title)
+        .bind(seed as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    let segments = challenges.iter().zip(weights).map(|(c, weight)| WheelSegment {
+        challenge_id: c.id,
+        title: c.title.clone(),
+        category: c.category,
+        difficulty: c.difficulty,
+        weight,
+    }).collect();
+    Ok(Ok(SpinResult { spin, index, segments, seed }))
+}
+
+// The weight of every challenge for this user. Categories the user scored low on in the
+// questionnaire get up to twice their weight, challenges spun recently get none
+// (unless that would leave nothing to draw).
+fn segment_weights(challenges: &[WheelChallenge], scores: &HashMap<ChallengeCategory, i32>, recent: &[Uuid]) -> Vec<f64> {
+    let base: Vec<f64> = challenges.iter().map(|c| {
+        let boost = match scores.get(&c.category) {
+            Some(score) => 1.0 + (QUESTIONNAIRE_MAX_SCORE - *score as f64) / QUESTIONNAIRE_MAX_SCORE,
+            None => 1.0,
+        };
+        c.weight.max(0.0) as f64 * boost
+    }).collect();
+
+    let fresh: Vec<f64> = challenges.iter().zip(&base)
+        .map(|(c, w)| if recent.contains(&c.id) { 0.0 } else { *w })
+        .collect();
+    if fresh.iter().any(|w| *w > 0.0) { fresh } else { base }
+}
+
+// The segment for `seed`: the first one where the running total of the weights
+// goes above seed / 2^32 * total weight. The client can redo this from the response.
+fn pick_segment(weights: &[f64], seed: u32) -> usize {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return seed as usize % weights.len();
+    }
+
+    let target = seed as f64 / 4294967296.0 * total;
+    let mut sum = 0.0;
+    for (i, w) in weights.iter().enumerate() {
+        sum += w;
+        if target < sum {
+            return i;
+        }
+    }
+    // Only reachable through rounding, land on the last segment that has any weight
+    weights.iter().rposition(|w| *w > 0.0).unwrap_or(0)
+}
+
+// The caller's spins, newest first
+pub async fn wheel_spin_history(headers: HeaderMap, State(state): State<AppState>) -> Result<Json<Vec<WheelSpin>>, StatusCode> {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return Err(StatusCode::UNAUTHORIZED)
+    };
+
+    let res = sqlx::query_as::<_, WheelSpin>("SELECT * FROM wheel_spins WHERE user_id = $1 ORDER BY spun_at DESC LIMIT $2;")
+        .bind(uid)
+        .bind(SPIN_HISTORY_SIZE)
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(s) => Ok(Json(s)),
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn wheel_spin_accept(headers: HeaderMap, State(state): State<AppState>, Path(id): Path<Uuid>) -> StatusCode {
+    change_spin_state(&state, &headers, id, &[SpinState::Spun], SpinState::Accepted).await
+}
+
+pub async fn wheel_spin_skip(headers: HeaderMap, State(state): State<AppState>, Path(id): Path<Uuid>) -> StatusCode {
+    change_spin_state(&state, &headers, id, &[SpinState::Spun, SpinState::Accepted], SpinState::Skipped).await
+}
+
+// Completing an accepted spin gives the challenge's points
+pub async fn wheel_spin_complete(headers: HeaderMap, State(state): State<AppState>, Path(id): Path<Uuid>) -> StatusCode {
+    let status = change_spin_state(&state, &headers, id, &[SpinState::Accepted], SpinState::Completed).await;
+    if status == StatusCode::OK {
+        let _ = update_streak(&state, &headers, ActivityKind::Wheel, 1).await;
+    }
+    status
+}
+
+async fn change_spin_state(state: &AppState, headers: &HeaderMap, id: Uuid, from: &[SpinState], to: SpinState) -> StatusCode {
+    let uid = match headers.get("user_id") {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+    let uid: Uuid = match uid.to_str().ok().and_then(|u| u.parse().ok()) {
+        Some(u) => u,
+        None => return StatusCode::UNAUTHORIZED
+    };
+
+    match change_spin_state_tx(state, uid, id, from, to).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn change_spin_state_tx(state: &AppState, uid: Uuid, id: Uuid, from: &[SpinState], to: SpinState) -> Result<StatusCode, sqlx::Error> {
+    let mut tx = state.db_connection.begin().await?;
+    let mut after = AfterCommit::default();
+
+    let current = sqlx::query_scalar::<_, SpinState>("SELECT state FROM wheel_spins WHERE id = $1 AND user_id = $2 FOR UPDATE;")
+        .bind(id)
+        .bind(uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+    match current {
+        None => return Ok(StatusCode::NOT_FOUND),
+        Some(current) if !from.contains(&current) => return Ok(StatusCode::CONFLICT),
+        Some(_) => {}
+    }
+
+    let (points, challenge_id) = if to == SpinState::Completed {
+        let spin = sqlx::query!(r#"SELECT COALESCE(c.points, 0) AS "points!", s.challenge_id FROM wheel_spins s LEFT JOIN wheel_challenges c ON c.id = s.challenge_id WHERE s.id = $1;"#, id)
+            .fetch_one(&mut *tx)
+            .await?;
+        (spin.points, spin.challenge_id)
+    } else {
+        (0, None)
+    };
+    sqlx::query("UPDATE wheel_spins SET state = $2, points_awarded = $3, updated_at = NOW() WHERE id = $1;")
+        .bind(id)
+        .bind(to)
+        .bind(points)
+        .execute(&mut *tx)
+        .await?;
+    if points > 0 {
+        award_points(&mut tx, state, &mut after, uid, points).await?;
+    }
+    if let Some(challenge_id) = challenge_id {
+        peer_challenges::wheel_completed(&mut tx, state, &mut after, uid, challenge_id).await?;
+    }
+    after.commit(tx, state).await?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn get_wheel_challanges(State(state): State<AppState>) -> (StatusCode, Json<Vec<WheelChallenge>>) {
+    match get_enabled_challenges(&state).await {
+        Ok(c) => (StatusCode::OK, Json(c)),
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
+        }
+    }
+}
+
+// All challenges, including the disabled ones
+pub async fn admin_wheel_challenges(State(state): State<AppState>) -> Result<Json<Vec<WheelChallenge>>, StatusCode> {
+    let res = sqlx::query_as::<_, WheelChallenge>("SELECT * FROM wheel_challenges ORDER BY position, title;")
+        .fetch_all(&state.db_connection)
+        .await;
+    match res {
+        Ok(c) => Ok(Json(c)),
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// New challenges go to the end of the wheel unless a position is given
+pub async fn admin_add_wheel_challenge(State(state): State<AppState>, Json(body): Json<WheelChallengeInput>) -> Result<Json<Uuid>, StatusCode> {
+    if body.weight.is_some_and(|w| w < 0.0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let res = sqlx::query_scalar::<_, Uuid>("INSERT INTO wheel_challenges (title, description, enabled, position, points, weight, category, difficulty) \
+        VALUES ($1, $2, $3, COALESCE($4, (SELECT COALESCE(MAX(position), 0) + 1 FROM wheel_challenges)), COALESCE($5, 30), COALESCE($6, 1), \
+        COALESCE($7, 'routine'), COALESCE($8, 'easy')) RETURNING id;")
+        .bind(body.title)
+        .bind(body.description)
+        .bind(body.enabled.unwrap_or(true))
+        .bind(body.position)
+        .bind(body.points)
+        .bind(body.weight)
+        .bind(body.category)
+        .bind(body.difficulty)
+        .fetch_one(&state.db_connection)
+        .await;
+    match res {
+        Ok(id) => {
+            invalidate_cache(&state).await;
+            Ok(Json(id))
+        }
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn admin_edit_wheel_challenge(Path(id): Path<Uuid>, State(state): State<AppState>, Json(body): Json<WheelChallengeInput>) -> StatusCode {
+    if body.weight.is_some_and(|w| w < 0.0) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let res = sqlx::query("UPDATE wheel_challenges SET title = $1, description = $2, enabled = COALESCE($3, enabled), position = COALESCE($4, position), \
+        points = COALESCE($5, points), weight = COALESCE($6, weight), category = COALESCE($7, category), difficulty = COALESCE($8, difficulty) WHERE id = $9;")
+        .bind(body.title)
+        .bind(body.description)
+        .bind(body.enabled)
+        .bind(body.position)
+        .bind(body.points)
+        .bind(body.weight)
+        .bind(body.category)
+        .bind(body.difficulty)
+        .bind(id)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(r) if r.rows_affected() == 0 => StatusCode::NOT_FOUND,
+        Ok(_) => {
+            invalidate_cache(&state).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn admin_delete_wheel_challenge(Path(id): Path<Uuid>, State(state): State<AppState>) -> StatusCode {
+    let res = sqlx::query!("DELETE FROM wheel_challenges WHERE id = $1;", id)
+        .execute(&state.db_connection)
+        .await;
+    match res {
+        Ok(_) => {
+            invalidate_cache(&state).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            eprintln!("wheel: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::time::Duration;
 
 use axum::{Router, middleware, routing::{delete, get, options, post, put}};
 use dotenv::dotenv;
@@ -22,13 +23,79 @@ async fn main() {
 
     let db_connection = PgPool::connect(url.as_str()).await.unwrap();
 
+    let wheel_spins_per_day = env::var("WHEEL_SPINS_PER_DAY").ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(3);
+    // Both have to grow or every point would be a new level
+    let level_curve = data::LevelCurve {
+        base_xp: env::var("LEVEL_BASE_XP").ok().and_then(|n| n.parse().ok()).filter(|n| *n > 0).unwrap_or(100),
+        growth: env::var("LEVEL_GROWTH").ok().and_then(|n| n.parse().ok()).filter(|n| *n >= 1.0).unwrap_or(1.0),
+    };
+
+    let streak_freeze_price = env::var("STREAK_FREEZE_PRICE").ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(100);
+
+    // Leaderboards are rebuilt from the database once they get older than this
+    let leaderboard_max_staleness = Duration::from_secs(env::var("LEADERBOARD_MAX_STALENESS_SECS").ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(60));
+
+    // Comments with any of these words are rejected, one word per line
+    let profanity = match env::var("PROFANITY_LIST_PATH") {
+        Ok(path) => std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("profanity list {}: {:?}", path, e);
+            String::new()
+        }),
+        Err(_) => String::new(),
+    };
+    let profanity = profanity.lines()
+        .map(|w| w.trim().to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    // Reminders are checked this often, "log" as the only channel just prints them
+    let reminder_interval = Duration::from_secs(env::var("REMINDER_INTERVAL_SECS").ok()
+        .and_then(|n| n.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(60));
+    let reminder_channels = handlers::reminders::channels(&env::var("REMINDER_CHANNELS").unwrap_or("in_app,email".to_string()));
+
+    // Web Push stays off until the VAPID keys are set
+    let push = match (env::var("VAPID_PUBLIC_KEY"), env::var("VAPID_PRIVATE_KEY")) {
+        (Ok(public), Ok(private)) => {
+            let subject = env::var("VAPID_SUBJECT").unwrap_or("mailto:admin@example.com".to_string());
+            Some(Arc::new(handlers::push::web_push(&public, &private, subject).expect("invalid VAPID keys")))
+        }
+        _ => None,
+    };
+    // A push service for trying out delivery locally, see handlers::push
+    let push_mock = env::var("PUSH_MOCK").is_ok_and(|v| v == "true").then(|| {
+        let url = env::var("PUSH_MOCK_URL").unwrap_or("http://localhost:7564/push/mock".to_string());
+        let url = reqwest::Url::parse(&url).expect("invalid PUSH_MOCK_URL");
+        Arc::new(data::MockPush { url: url.as_str().trim_end_matches('/').to_string(), endpoints: Mutex::new(std::collections::HashMap::new()) })
+    });
+
     let state = data::AppState {
         db_connection: db_connection.clone(),
         weekly_challange: Arc::new(Mutex::new(None)),
         last_week: Arc::new(Mutex::new(None)),
+        wheel_challenges: Arc::new(Mutex::new(None)),
+        wheel_spins_per_day,
+        level_curve,
+        streak_freeze_price,
+        leaderboards: Arc::new(Mutex::new(data::LeaderboardCache::default())),
+        leaderboard_writes: Arc::new(data::LeaderboardWrites::default()),
+        leaderboard_max_staleness,
+        profanity: Arc::new(profanity),
+        events: data::EventHub { sender: tokio::sync::broadcast::channel(handlers::events::HUB_CAPACITY).0 },
+        push,
+        push_mock,
     };
 
 
+    tokio::spawn(handlers::reminders::scheduler(state.clone(), reminder_channels, reminder_interval));
+
     let t: data::User = query_as!(data::User, "SELECT * FROM users;").fetch_one(&state.db_connection).await.unwrap();
 
     println!("{:?}", t);
@@ -40,17 +107,69 @@ async fn main() {
         .route("/me", get(handlers::me))
         .route("/get_random_question", get(handlers::get_weekly_quest))
         .route("/send_form_points", post(handlers::send_form_points))
+        .route("/questionnaire", post(handlers::send_questionnaire))
+        .route("/level_ups", get(handlers::levels::level_ups))
+        .route("/achievements", get(handlers::achievements::get_achievements))
         .route("/get_weekly", get(handlers::get_weekly_quest))
-        .route("/wheel/challenges", get(handlers::get_wheel_challanges))
-        .route("/wheel/spin", get(handlers::wheel_spin))
-        .route("/streak", get(handlers::get_streak))
+        .route("/wheel/challenges", get(handlers::wheel::get_wheel_challanges))
+        .route("/wheel/spin", post(handlers::wheel::wheel_spin))
+        .route("/wheel/spins", get(handlers::wheel::wheel_spin_history))
+        .route("/wheel/spins/{id}/accept", post(handlers::wheel::wheel_spin_accept))
+        .route("/wheel/spins/{id}/complete", post(handlers::wheel::wheel_spin_complete))
+        .route("/wheel/spins/{id}/skip", post(handlers::wheel::wheel_spin_skip))
+        .route("/streak", get(handlers::streaks::get_streak))
+        .route("/streak/freezes", post(handlers::streaks::buy_streak_freeze))
+        .route("/streak/repair", post(handlers::streaks::repair_streak))
+        .route("/streak/calendar", get(handlers::streaks::streak_calendar))
         .route("/goals", post(handlers::pchallange_create))
         .route("/goals", get(handlers::pchallange_get))
         .route("/diary", post(handlers::diary_create))
         .route("/diary", get(handlers::diary_get))
         .route("/diary/{id}", delete(handlers::diary_delete))
-        .route("/goals/{id}", delete(handlers::pchallange_delete))
-        .route("/leaderboard", get(handlers::leaderboard));
+        .route("/goals/{id}", put(handlers::pchallange_update).delete(handlers::pchallange_delete))
+        .route("/leaderboard", get(handlers::leaderboard::leaderboard))
+        .route("/settings/privacy", get(handlers::settings::get_privacy).put(handlers::settings::update_privacy))
+        .route("/settings/reminders", get(handlers::reminders::get_reminder_settings).put(handlers::reminders::update_reminder_settings))
+        .route("/avatars", get(handlers::settings::avatars))
+        .route("/friends", get(handlers::friends::list_friends).post(handlers::friends::send_friend_request))
+        .route("/friends/requests", get(handlers::friends::friend_requests))
+        .route("/friends/blocked", get(handlers::friends::blocked_users))
+        .route("/friends/{id}", delete(handlers::friends::remove_friend))
+        .route("/friends/{id}/accept", post(handlers::friends::accept_friend_request))
+        .route("/friends/{id}/decline", post(handlers::friends::decline_friend_request))
+        .route("/friends/{id}/block", post(handlers::friends::block_user))
+        .route("/groups", get(handlers::groups::my_groups).post(handlers::groups::create_group))
+        .route("/groups/join", post(handlers::groups::join_group))
+        .route("/groups/leaderboard", get(handlers::groups::group_leaderboard))
+        .route("/groups/{id}", get(handlers::groups::group_details).put(handlers::groups::edit_group).delete(handlers::groups::delete_group))
+        .route("/groups/{id}/leave", post(handlers::groups::leave_group))
+        .route("/groups/{id}/invite_code", post(handlers::groups::new_invite_code))
+        .route("/groups/{id}/members/{member}", put(handlers::groups::set_member_role).delete(handlers::groups::remove_member))
+        .route("/groups/{id}/feed", get(handlers::groups::group_feed))
+        .route("/groups/{id}/quests", get(handlers::groups::group_quests).post(handlers::groups::add_group_quest))
+        .route("/challenges", get(handlers::peer_challenges::peer_challenges).post(handlers::peer_challenges::create_peer_challenge))
+        .route("/challenges/{id}/accept", post(handlers::peer_challenges::accept_peer_challenge))
+        .route("/challenges/{id}/decline", post(handlers::peer_challenges::decline_peer_challenge))
+        .route("/challenges/{id}/complete", post(handlers::peer_challenges::complete_peer_challenge))
+        .route("/feed", get(handlers::feed::feed))
+        .route("/feed/{id}/hide", post(handlers::feed::hide_event).delete(handlers::feed::unhide_event))
+        .route("/feed/{id}/reactions", get(handlers::reactions::reactions))
+        .route("/feed/{id}/reactions/{reaction}", put(handlers::reactions::add_reaction).delete(handlers::reactions::remove_reaction))
+        .route("/feed/{id}/comments", get(handlers::comments::comments).post(handlers::comments::add_comment))
+        .route("/comments/{id}", delete(handlers::comments::delete_comment))
+        .route("/comments/{id}/report", post(handlers::comments::report_comment))
+        .route("/events", get(handlers::events::events))
+        .route("/push/vapid_key", get(handlers::push::vapid_key))
+        .route("/push/subscriptions", get(handlers::push::push_subscriptions).post(handlers::push::register_subscription))
+        .route("/push/subscriptions/{id}", delete(handlers::push::unregister_subscription))
+        .route("/notifications", get(handlers::notifications::notifications))
+        .route("/notifications/unread", get(handlers::notifications::unread_count))
+        .route("/notifications/read", post(handlers::notifications::mark_all_read))
+        .route("/notifications/preferences", get(handlers::notifications::get_preferences).put(handlers::notifications::update_preferences))
+        .route("/notifications/{id}/read", post(handlers::notifications::mark_read))
+        .route("/quests/{id}/checkin", post(handlers::progress::checkin))
+        .route("/campaigns", get(handlers::campaigns::list_campaigns))
+        .route("/campaigns/{id}/leaderboard", get(handlers::campaigns::campaign_leaderboard));
 
     
     let admin = Router::new()
@@ -65,6 +184,21 @@ async fn main() {
         .route("/api/challenges", post(handlers::admin_add_challange))
         .route("/api/challenges/{id}", put(handlers::admin_edit_challange))
         .route("/api/challenges/{id}", delete(handlers::admin_delete_challange))
+        .route("/api/wheel", get(handlers::wheel::admin_wheel_challenges))
+        .route("/api/wheel", post(handlers::wheel::admin_add_wheel_challenge))
+        .route("/api/wheel/{id}", put(handlers::wheel::admin_edit_wheel_challenge))
+        .route("/api/wheel/{id}", delete(handlers::wheel::admin_delete_wheel_challenge))
+        .route("/api/activity_rules", get(handlers::streaks::admin_activity_rules))
+        .route("/api/metrics/leaderboard", get(handlers::leaderboard::admin_leaderboard_metrics))
+        .route("/api/activity_rules/{kind}", put(handlers::streaks::admin_edit_activity_rule))
+        .route("/api/campaigns", get(handlers::campaigns::admin_campaigns))
+        .route("/api/campaigns", post(handlers::campaigns::admin_add_campaign))
+        .route("/api/campaigns/{id}", put(handlers::campaigns::admin_edit_campaign))
+        .route("/api/campaigns/{id}", delete(handlers::campaigns::admin_delete_campaign))
+        .route("/api/campaigns/{id}/quests/{qid}", post(handlers::campaigns::admin_add_campaign_quest))
+        .route("/api/campaigns/{id}/quests/{qid}", delete(handlers::campaigns::admin_remove_campaign_quest))
+        .route("/api/reports", get(handlers::comments::admin_reports))
+        .route("/api/reports/{id}/resolve", post(handlers::comments::admin_resolve_report))
         .route_layer(middleware::from_fn_with_state(state.clone(), handlers::admin_check));
 
 
@@ -72,6 +206,9 @@ async fn main() {
         .route("/", get(|| async {StatusCode::IM_A_TEAPOT}))
         .nest("/api", api)
         .nest("/admin", admin)
+        .route("/push/mock", post(handlers::push::mock_subscribe))
+        .route("/push/mock/{id}", post(handlers::push::mock_receive).get(handlers::push::mock_messages).delete(handlers::push::mock_unsubscribe))
+        .route("/push/mock/{id}/status", put(handlers::push::mock_set_status))
         .route("/challange/receive", get(handlers::request_challange))
         .route("/challange/send/{id}", post(handlers::send_challange))
         .route("/{*wildcard}", options(|| async { StatusCode::NO_CONTENT }))
@@ -95,7 +232,7 @@ async fn cors(req: Request<Body>, next: Next) -> impl IntoResponse {
         // Explicitly allow the custom header `user_id` and common headers
         headers.insert(
             "Access-Control-Allow-Headers",
-            HeaderValue::from_static("Content-Type, Authorization, user_id"),
+            HeaderValue::from_static("Content-Type, Authorization, user_id, Idempotency-Key"),
         );
         return (StatusCode::OK, headers, "").into_response();
     }
@@ -106,7 +243,7 @@ async fn cors(req: Request<Body>, next: Next) -> impl IntoResponse {
     headers.insert("Access-Control-Allow-Methods", HeaderValue::from_static("GET,POST,OPTIONS,PUT,DELETE"));
     headers.insert(
         "Access-Control-Allow-Headers",
-        HeaderValue::from_static("Content-Type, Authorization, user_id"),
+        HeaderValue::from_static("Content-Type, Authorization, user_id, Idempotency-Key"),
     );
     res
 }
\ No newline at end of file
@@ -34,8 +34,9 @@ function App() {
             username: backendUser.name || '',
             email: backendUser.mail || '',
             totalXp: backendUser.points || 0,
-            currentXp: (backendUser.points || 0) % 100,
-            level: Math.floor((backendUser.points || 0) / 100) + 1,
+            currentXp: backendUser.xp_into_level || 0,
+            level: backendUser.level || 1,
+            xpForNextLevel: backendUser.xp_for_next_level || 0,
             createdAt: backendUser.created_at || new Date().toISOString(),
             is_admin: backendUser.is_admin || false,
           }
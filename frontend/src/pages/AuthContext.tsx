@@ -27,8 +27,9 @@ export function AuthProvider({ children }: { children: React.ReactNode }) {
             username: me.name || me.username || '',
             email: me.mail || '',
             totalXp: me.points || 0,
-            currentXp: (me.points || 0) % 100,
-            level: Math.floor((me.points || 0) / 100) + 1,
+            currentXp: me.xp_into_level || 0,
+            level: me.level || 1,
+            xpForNextLevel: me.xp_for_next_level || 0,
             createdAt: me.created_at || new Date().toISOString(),
             is_admin: me.is_admin || false,
           };
@@ -1,10 +1,9 @@
 import React, { useEffect, useRef, useState } from "react";
 import { useNavigate } from "react-router-dom";
-
-
-const API_BASE = "http://localhost:5174";
+import api from "../services/api";
 
 type Challenge = {
+  id?: string;
   title: string;
   description?: string;
   tags?: string[];
@@ -235,6 +234,7 @@ export default function Challenges() {
   const [angle, setAngle] = useState(0);
   const [history, setHistory] = useState<HistoryItem[]>([]);
   const [completed, setCompleted] = useState<string[]>([]);
+  const [spinId, setSpinId] = useState<string | null>(null);
   const wheelRef = useRef<HTMLDivElement>(null);
   const [menuOpen, setMenuOpen] = useState(false);
   const navigate = useNavigate();
@@ -245,9 +245,8 @@ export default function Challenges() {
   };
 
   useEffect(() => {
-    fetch(`${API_BASE}/challenges`)
-    .then((r) => r.json())
-    .then((data: Challenge[]) => setChallenges(data))
+    api.request<Challenge[]>("/api/wheel/challenges")
+    .then((data) => setChallenges(data))
     .catch(() => setChallenges(localFallback));
   }, []);
 
@@ -257,13 +256,17 @@ export default function Challenges() {
     if (isSpinning || challenges.length === 0) return;
     setIsSpinning(true);
 
-    let index = Math.floor(Math.random() * challenges.length);
-
+    // The server picks the segment and keeps track of the spin
+    let index: number;
     try {
-      const resp = await fetch(`${API_BASE}/spin`, { method: "POST" });
-      const data = (await resp.json()) as { index?: number };
-      if (typeof data?.index === "number") index = data.index % challenges.length;
-    } catch {}
+      const data = await api.request<{ index: number; spin: { id: string } }>("/api/wheel/spin", { method: "POST" });
+      index = data.index % challenges.length;
+      setSpinId(data.spin.id);
+    } catch (e) {
+      console.error("Failed to spin the wheel:", e);
+      setIsSpinning(false);
+      return;
+    }
 
     const sector = 360 / challenges.length;
     const chosenSectorCenter = index * sector + sector / 2;
@@ -298,14 +301,15 @@ export default function Challenges() {
   };
 
   const markDone = async (c: Challenge) => {
-    setCompleted((prev) => (prev.includes(c.title) ? prev : [...prev, c.title]));
+    if (!spinId) return;
     try {
-      await fetch(`${API_BASE}/complete`, {
-        method: "POST",
-        headers: { "Content-Type": "application/json" },
-        body: JSON.stringify({ title: c.title }),
-      });
-    } catch {}
+      await api.request(`/api/wheel/spins/${spinId}/accept`, { method: "POST" });
+      await api.request(`/api/wheel/spins/${spinId}/complete`, { method: "POST" });
+      setCompleted((prev) => (prev.includes(c.title) ? prev : [...prev, c.title]));
+      setSpinId(null);
+    } catch (e) {
+      console.error("Failed to complete the challenge:", e);
+    }
   };
 
   const resetCompleted = () => setCompleted([]);
@@ -8,10 +8,13 @@ import { useAuth } from "../pages/AuthContext";
 
 
 type LeaderboardEntry = {
-  id: string
+  position: number
   username: string
+  avatar: string | null
   totalXp: number
   level: number
+  rank: number
+  isMe: boolean
 }
 
 type StreakData = {
@@ -35,6 +38,7 @@ export default function DashboardPage({ user }: DashboardPageProps) {
   const [menuOpen, setMenuOpen] = useState(false)
   const [leaderboard, setLeaderboard] = useState<LeaderboardEntry[]>([])
   const [loadingLeaderboard, setLoadingLeaderboard] = useState(true)
+  const [myRank, setMyRank] = useState(0)
   const [streak, setStreak] = useState<StreakData>({ currentStreak: 0, longestStreak: 0, lastCompletedDate: '' })
   const [loadingStreak, setLoadingStreak] = useState(true)
   const navigate = useNavigate()
@@ -149,14 +153,18 @@ export default function DashboardPage({ user }: DashboardPageProps) {
       try {
         const response = await api.request('/api/leaderboard')
         console.log(response);
-        if (response && Array.isArray(response)) {
-          const mapped: LeaderboardEntry[] = response.map((entry: any) => ({
-            id: entry[2],
-            username: entry[0] || 'Unknown',
-            totalXp: entry[1] || 0,
-            level: Math.floor((entry[1] || 0) / 100) + 1,
+        if (response && Array.isArray(response.entries)) {
+          const mapped: LeaderboardEntry[] = response.entries.map((entry: any) => ({
+            position: entry.position,
+            username: entry.name || 'Unknown',
+            avatar: entry.avatar ?? null,
+            totalXp: entry.points || 0,
+            level: entry.level || 1,
+            rank: entry.rank,
+            isMe: !!entry.is_me,
           }))
           setLeaderboard(mapped)
+          setMyRank(response.me?.rank || 0)
         }
       } catch (e) {
         console.warn('Failed to fetch leaderboard', e)
@@ -206,7 +214,7 @@ export default function DashboardPage({ user }: DashboardPageProps) {
     }
   }, [location, navigate])
 
-  const userRank = leaderboard.findIndex((entry) => entry.id === user?.id) + 1
+  const userRank = myRank
 
   const handleLogout = () => {
     localStorage.removeItem('authToken')
@@ -415,27 +423,27 @@ export default function DashboardPage({ user }: DashboardPageProps) {
               <div style={{ color: '#666', fontSize: '13px' }}>No leaderboard data available</div>
             ) : (
               <div style={styles.leaderboardList}>
-                {leaderboard.slice(0, 10).map((entry, index) => (
+                {leaderboard.map((entry) => (
                   <div
-                    key={entry.id}
+                    key={entry.position}
                     style={{
                       ...styles.leaderboardItem,
-                      background: entry.id === user?.id ? '#e8f5e9' : 'transparent',
-                      borderLeft: entry.id === user?.id ? '4px solid #19c916ff' : '4px solid transparent',
+                      background: entry.isMe ? '#e8f5e9' : 'transparent',
+                      borderLeft: entry.isMe ? '4px solid #19c916ff' : '4px solid transparent',
                     }}
                   >
                     <div style={styles.leaderboardRank}>
                       <span style={{
                         ...styles.leaderboardRankBadge,
-                        background: index === 0 ? '#fbbf24' : index === 1 ? '#c0cfe2' : index === 2 ? '#d97706' : '#9ca3af',
+                        background: entry.rank === 1 ? '#fbbf24' : entry.rank === 2 ? '#c0cfe2' : entry.rank === 3 ? '#d97706' : '#9ca3af',
                       }}>
-                        {index + 1}
+                        {entry.rank}
                       </span>
                     </div>
                     <div style={styles.leaderboardInfo}>
                       <div style={styles.leaderboardName}>
                         {entry.username}
-                        {entry.id === user?.id && ' (You)'}
+                        {entry.isMe && ' (You)'}
                       </div>
                       <div style={styles.leaderboardLevel}>Lvl {entry.level}</div>
                     </div>
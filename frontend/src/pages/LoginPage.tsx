@@ -35,10 +35,10 @@ export default function LoginPage() {
                   id: backendUser.id,
                   username: backendUser.name || data.user.username,
                   email: backendUser.mail || data.user.email,
-                  // Derive level from points (simple rule: 100 XP per level)
                   totalXp: backendUser.points || 0,
-                  currentXp: (backendUser.points || 0) % 100,
-                  level: Math.floor((backendUser.points || 0) / 100) + 1,
+                  currentXp: backendUser.xp_into_level || 0,
+                  level: backendUser.level || 1,
+                  xpForNextLevel: backendUser.xp_for_next_level || 0,
                   createdAt: backendUser.created_at || new Date().toISOString(),
                   is_admin: backendUser.is_admin || false,
                 }
@@ -2,9 +2,11 @@ import { useState, useEffect, useMemo } from 'react'
 import { useNavigate } from 'react-router-dom'
 
 type Badge = {
-  id: number
+  id: string
   name: string
-  xpRequired: number
+  description: string
+  progress: number
+  threshold: number
   imagePath: string
   unlocked: boolean
 }
@@ -17,6 +19,32 @@ const getScreenTimePoints = (hours: number) => {
   return 0
 }
 
+type Category = 'mental' | 'digital' | 'nature' | 'routine' | 'selfcare'
+
+// Which wheel category each question tells something about. Questions that ask what the user
+// would like (10, 37, 49) don't say how they are doing, so they aren't scored.
+const questionCategories: Record<number, Category> = {
+  1: 'mental', 2: 'mental', 3: 'routine', 4: 'digital', 5: 'digital', 6: 'digital', 7: 'nature', 8: 'nature', 9: 'selfcare',
+  11: 'mental', 12: 'mental', 13: 'mental', 14: 'mental', 15: 'mental',
+  16: 'routine', 17: 'routine', 18: 'routine', 19: 'routine', 20: 'routine',
+  21: 'digital', 22: 'digital', 23: 'digital', 24: 'digital', 25: 'digital',
+  26: 'mental', 27: 'mental', 28: 'mental', 29: 'mental', 30: 'mental',
+  31: 'nature', 32: 'nature', 33: 'nature', 34: 'nature',
+  35: 'selfcare', 36: 'selfcare', 38: 'selfcare', 39: 'selfcare',
+  40: 'mental', 41: 'mental', 42: 'mental',
+  43: 'routine', 44: 'routine', 45: 'routine', 46: 'routine',
+  47: 'selfcare', 48: 'mental', 50: 'mental',
+}
+
+// Options go from doing well to struggling, except for these questions
+const reversedQuestions = [6, 31, 39]
+
+// 0 (struggling) to 4 (doing well), like questionnaire_scores on the backend
+const answerScore = (questionId: number, index: number, options: number) => {
+  const position = reversedQuestions.includes(questionId) ? index : options - 1 - index
+  return (position / (options - 1)) * 4
+}
+
 export default function QuestionsHealth() {
   const [responses, setResponses] = useState<Record<number, string>>({})
   const [screenTime, setScreenTime] = useState<number>(0)
@@ -65,14 +93,18 @@ export default function QuestionsHealth() {
             '/src/assets/18.png',
           ]
 
-                  
-          const totalBadges = Math.ceil(currentXp / 50) + 3 
-          const badgeList: Badge[] = Array.from({ length: totalBadges }, (_, i) => ({
-            id: i,
-            name: `Badge ${i + 1}`,
-            xpRequired: (i + 1) * 50,
-            imagePath: badgeImages[i % badgeImages.length], 
-            unlocked: currentXp >= (i + 1) * 50,
+          const achRes = await fetch(`${apiBase}/api/achievements`, {
+            headers: { user_id: userId },
+          })
+          const achievements: any[] = achRes.ok ? await achRes.json() : []
+          const badgeList: Badge[] = achievements.map((a, i) => ({
+            id: a.id,
+            name: a.name,
+            description: a.description,
+            progress: a.progress,
+            threshold: a.threshold,
+            imagePath: badgeImages[i % badgeImages.length],
+            unlocked: a.unlocked,
           }))
 
           setBadges(badgeList)
@@ -374,32 +406,34 @@ export default function QuestionsHealth() {
       return
     }
     try {
-      let totalPoints = 0
+      const answers: Record<string, number[]> = {}
       for (const q of dailyQuestions) {
-        const ans = responses[q.id]
-        if (!ans) continue
-        const idx = q.options.findIndex((o) => o === ans)
-        if (idx >= 0) totalPoints += (idx + 1)
+        const category = questionCategories[q.id]
+        const idx = q.options.findIndex((o) => o === responses[q.id])
+        if (!category || idx < 0) continue
+        answers[category] = [...(answers[category] || []), answerScore(q.id, idx, q.options.length)]
       }
+      answers.digital = [...(answers.digital || []), (getScreenTimePoints(screenTime) / 10) * 4]
 
-    
-      const screenTimePoints = getScreenTimePoints(screenTime)
-      totalPoints += screenTimePoints
+      const scores: Record<string, number> = {}
+      for (const [category, values] of Object.entries(answers)) {
+        scores[category] = Math.round(values.reduce((a, b) => a + b, 0) / values.length)
+      }
 
       const apiBase = import.meta.env.VITE_API_URL || ''
       const userId = localStorage.getItem('authToken') || ''
 
-      const res = await fetch(`${apiBase}/api/send_form_points`, {
+      const res = await fetch(`${apiBase}/api/questionnaire`, {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
           user_id: userId,
         },
-        body: JSON.stringify(totalPoints),
+        body: JSON.stringify({ scores }),
       })
 
       if (!res.ok) {
-        console.error('Failed to send points to backend')
+        console.error('Failed to send questionnaire to backend')
         return
       }
 
@@ -472,7 +506,7 @@ export default function QuestionsHealth() {
         <div style={styles.badgesSection}>
           <h2 style={styles.badgesTitle}> Tвоите значки</h2>
           <p style={{ color: '#666', marginBottom: '16px' }}>
-            Отключете значка всеки 50 XP! Вие имате <strong>{userXp} XP</strong>
+            Отключвайте значки с предизвикателства, серии и записи в дневника! Вие имате <strong>{userXp} XP</strong>
           </p>
           <div style={styles.badgesContainer}>
             {badges.map((badge) => (
@@ -483,7 +517,7 @@ export default function QuestionsHealth() {
                   opacity: badge.unlocked ? 1 : 0.4,
                   filter: badge.unlocked ? 'none' : 'grayscale(100%)',
                 }}
-                title={badge.unlocked ? 'Отключено' : `Отключете на ${badge.xpRequired} XP`}
+                title={badge.description}
               >
                 <img
                   src={badge.imagePath}
@@ -494,11 +528,11 @@ export default function QuestionsHealth() {
                   }}
                 />
                 <div style={{ textAlign: 'center', marginTop: '8px', fontSize: '12px' }}>
-                  <div style={{ color: '#666' }}>{badge.xpRequired} XP</div>
+                  <div style={{ color: '#666' }}>{badge.name}</div>
                   {badge.unlocked ? (
                     <div style={{ color: '#4CAF50', fontWeight: 'bold' }}>✓ Отключено</div>
                   ) : (
-                    <div style={{ color: '#999' }}>Заключено</div>
+                    <div style={{ color: '#999' }}>{badge.progress} / {badge.threshold}</div>
                   )}
                 </div>
               </div>
@@ -27,7 +27,7 @@ export default function YourGoals() {
   const [formData, setFormData] = useState({
     title: '',
     description: '',
-    category: 'Personal',
+    category: 'personal',
   })
 
   // Diary state
@@ -53,7 +53,7 @@ export default function YourGoals() {
             id: goal.id || goal[0],
             title: goal.title || goal.name || goal[1] || 'Goal',
             description: goal.description || goal[2] || '',
-            category: goal.category || goal[6] || 'Personal',
+            category: goal.category || goal[6] || 'personal',
             dueDate: goal.due_date || goal.dueDate || goal[7] || '',
             createdAt: goal.created_at || goal.createdAt || '',
           }))
@@ -113,7 +113,7 @@ export default function YourGoals() {
           name: formData.title,
           description: formData.description,
           category: formData.category,
-          priority: 0,
+          priority: 3,
         }),
       })
 
@@ -128,7 +128,7 @@ export default function YourGoals() {
           dueDate: newGoal.dueDate || '',
         }
         setGoals([...goals, mappedGoal])
-        setFormData({ title: '', description: '', category: 'Personal' })
+        setFormData({ title: '', description: '', category: 'personal' })
         setShowForm(false)
       }
     } catch (e) {
@@ -227,14 +227,26 @@ export default function YourGoals() {
     return colors[mood] || '#2196F3'
   }
 
+  const getCategoryLabel = (category: string): string => {
+    const labels: Record<string, string> = {
+      personal: 'Лична цел',
+      health: 'Здраве',
+      work: 'Работа',
+      learning: 'Учене',
+      fitness: 'Спортуване',
+      mindfulness: 'Осъзнатост',
+    }
+    return labels[category] || category
+  }
+
   const getCategoryColor = (category: string): string => {
     const colors: Record<string, string> = {
-      Personal: '#2196F3',
-      Health: '#4CAF50',
-      Work: '#FF9800',
-      Learning: '#9C27B0',
-      Fitness: '#F44336',
-      Mindfulness: '#00BCD4',
+      personal: '#2196F3',
+      health: '#4CAF50',
+      work: '#FF9800',
+      learning: '#9C27B0',
+      fitness: '#F44336',
+      mindfulness: '#00BCD4',
     }
     return colors[category] || '#2196F3'
   }
@@ -313,12 +325,12 @@ export default function YourGoals() {
                   onChange={(e) => setFormData({ ...formData, category: e.target.value })}
                   style={styles.select}
                 >
-                  <option>Лична цел</option>
-                  <option>Здраве</option>
-                  <option>Работа</option>
-                  <option>Учене</option>
-                  <option>Спортуване</option>
-                  <option>Осъзнатост</option>
+                  <option value="personal">Лична цел</option>
+                  <option value="health">Здраве</option>
+                  <option value="work">Работа</option>
+                  <option value="learning">Учене</option>
+                  <option value="fitness">Спортуване</option>
+                  <option value="mindfulness">Осъзнатост</option>
                 </select>
               </div>
             </div>
@@ -358,7 +370,7 @@ export default function YourGoals() {
                         backgroundColor: getCategoryColor(goal.category),
                       }}
                     >
-                      {goal.category}
+                      {getCategoryLabel(goal.category)}
                     </span>
                   </div>
                   <button
@@ -5,6 +5,7 @@ export interface User {
   level: number
   totalXp: number
   currentXp: number
+  xpForNextLevel?: number
   createdAt: string
   is_admin?: boolean
 }